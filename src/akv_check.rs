@@ -0,0 +1,75 @@
+use libactionkv::ActionKV;
+
+#[cfg(target_os = "windows")]
+const USAGE: &str = "
+Usage:
+    akv_check.exe DIR
+    akv_check.exe DIR --repair
+
+Prints a JSON report on the store in DIR. Exits with 1 if any record
+is corrupt and 2 if the store couldn't be read.
+
+With --repair, damaged segments are rewritten with every record that
+can be salvaged, keeping the originals as *.log.corrupt, and a JSON
+report of what was lost is printed instead. The store must not be
+open elsewhere.
+";
+
+#[cfg(not(target_os = "windows"))]
+const USAGE: &str = "
+Usage:
+    akv_check DIR
+    akv_check DIR --repair
+
+Prints a JSON report on the store in DIR. Exits with 1 if any record
+is corrupt and 2 if the store couldn't be read.
+
+With --repair, damaged segments are rewritten with every record that
+can be salvaged, keeping the originals as *.log.corrupt, and a JSON
+report of what was lost is printed instead. The store must not be
+open elsewhere.
+";
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let fname = args.get(1).expect(USAGE);
+
+    let repair = match args.get(2).map(String::as_str) {
+        None => false,
+        Some("--repair") => true,
+        Some(_) => {
+            eprintln!("{}", &USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let fpath = std::path::Path::new(&fname);
+    if repair {
+        let report = match ActionKV::repair(fpath) {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("unable to repair {}: {}", fpath.display(), err);
+                std::process::exit(2);
+            }
+        };
+
+        let json = serde_json::to_string_pretty(&report).expect("unable to encode report");
+        println!("{}", json);
+        return;
+    }
+
+    let report = match ActionKV::check(fpath) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("unable to check {}: {}", fpath.display(), err);
+            std::process::exit(2);
+        }
+    };
+
+    let json = serde_json::to_string_pretty(&report).expect("unable to encode report");
+    println!("{}", json);
+
+    if report.is_corrupt() {
+        std::process::exit(1);
+    }
+}
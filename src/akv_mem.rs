@@ -3,26 +3,26 @@ use libactionkv::ActionKV;
 #[cfg(target_os = "windows")]
 const USAGE: &str = "
 Usage:
-    akv_mem.exe FILE get KEY
-    akv_mem.exe FILE delete KEY
-    akv_mem.exe FILE insert KEY VALUE
-    akv_mem.exe FILE update KEY VALUE
+    akv_mem.exe DIR get KEY
+    akv_mem.exe DIR delete KEY
+    akv_mem.exe DIR insert KEY VALUE
+    akv_mem.exe DIR update KEY VALUE
 ";
 
 #[cfg(not(target_os = "windows"))]
 const USAGE: &str = "
 Usage
-    akv_mem FILE get KEY
-    akv_mem FILE delete KEY
-    akv_mem FILE insert KEY VALUE
-    akv_mem FILE update KEY VALUE
+    akv_mem DIR get KEY
+    akv_mem DIR delete KEY
+    akv_mem DIR insert KEY VALUE
+    akv_mem DIR update KEY VALUE
 ";
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let fname = args.get(1).expect(&USAGE);
-    let action = args.get(2).expect(&USAGE).as_ref();
-    let key = args.get(3).expect(&USAGE).as_ref();
+    let fname = args.get(1).expect(USAGE);
+    let action = args.get(2).expect(USAGE).as_ref();
+    let key = args.get(3).expect(USAGE).as_ref();
     let pos_value = args.get(4);
 
     let fpath = std::path::Path::new(&fname);
@@ -40,11 +40,11 @@ fn main() {
         },
         "delete" => store.delete(key).unwrap(),
         "insert" => {
-            let value = pos_value.expect(&USAGE).as_ref();
+            let value = pos_value.expect(USAGE).as_ref();
             store.insert(key, value).unwrap();
         }
         "update" => {
-            let value = pos_value.expect(&USAGE).as_ref();
+            let value = pos_value.expect(USAGE).as_ref();
             store.update(key, value).unwrap();
         }
         _ => eprintln!("{}", &USAGE),
@@ -0,0 +1,33 @@
+use crate::{ByteStr, ByteString};
+
+/// A group of inserts and deletes applied to the store as a single unit
+///
+/// `ActionKV::write_batch` writes the whole batch inside one checksummed record, so after a
+/// crash `load` sees either every operation in it or none of them.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    /// Operations in the order they were added; a `None` value is a delete
+    pub(crate) ops: Vec<(ByteString, Option<ByteString>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) {
+        self.ops.push((key.to_vec(), Some(value.to_vec())));
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) {
+        self.ops.push((key.to_vec(), None));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
@@ -0,0 +1,322 @@
+//! Read-only audit of a store directory, as run by `akv_check`
+//!
+//! Every record in every segment is read back and its checksum verified, without opening the
+//! store: nothing is upgraded, truncated or cleaned up along the way, so the files are left
+//! exactly as they were found.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::encryption::Decryptor;
+use crate::header::{header_len, Header, FORMAT_VERSION, HEADER_LEN};
+use crate::segment::{self, Segment};
+use crate::{upgrade, ActionKV, ActionKvError, ByteString, ChecksumKind, Record, Result};
+
+/// What `ActionKV::check` found in a store directory
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CheckReport {
+    pub dir: PathBuf,
+    pub segments: Vec<SegmentReport>,
+    /// Files left behind by an interrupted merge, which opening the store removes
+    pub leftovers: Vec<PathBuf>,
+    /// Values and tombstones read back, counting each record in a batch separately
+    pub records: u64,
+    pub values: u64,
+    pub tombstones: u64,
+    pub batches: u64,
+    /// Values whose TTL has run out
+    pub expired: u64,
+    /// Records for a key that is written again later in the log
+    pub duplicates: u64,
+    /// Damaged records; see `SegmentReport::corrupt`
+    pub corrupt: u64,
+    /// Keys with a live value
+    pub live_keys: u64,
+    /// Size of the records holding live values
+    pub live_bytes: u64,
+    /// Size of every segment, headers included
+    pub total_bytes: u64,
+    /// `total_bytes` over `live_bytes`, or `None` for a store with nothing live in it
+    pub space_amplification: Option<f64>,
+}
+
+impl CheckReport {
+    /// Whether any record is damaged or any segment header couldn't be read
+    ///
+    /// A torn tail at the end of the newest segment is not counted: it is what a crash part
+    /// way through a write leaves behind, and `load` recovers from it by design.
+    pub fn is_corrupt(&self) -> bool {
+        self.corrupt > 0 || self.segments.iter().any(|s| s.header_error.is_some())
+    }
+}
+
+/// What `ActionKV::check` found in a single segment
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SegmentReport {
+    pub id: u32,
+    pub first: u32,
+    pub path: PathBuf,
+    pub len: u64,
+    /// `None` if the header couldn't be read
+    pub format_version: Option<u16>,
+    /// Why the header couldn't be read; the segment's records are not scanned
+    pub header_error: Option<String>,
+    /// Whether the segment was written in an older format, which opening the store upgrades
+    ///
+    /// Its records are checked as they will be once upgraded, so the offsets reported for it
+    /// are offsets into the upgraded segment. The exception is a record with a damaged length,
+    /// which stops the upgrade: it is reported at its offset in the segment as it stands, and
+    /// nothing else in the segment is checked.
+    pub outdated: bool,
+    pub records: u64,
+    pub values: u64,
+    pub tombstones: u64,
+    pub batches: u64,
+    /// Records that failed their checksum, and records cut short in a segment other than the
+    /// newest, which has to have been complete when it was sealed
+    pub corrupt: Vec<CorruptRecord>,
+    /// Record cut short at the very end of the newest segment
+    pub torn_tail: Option<TornTail>,
+}
+
+/// A damaged record; see `ActionKvError::Corruption`
+///
+/// The checksums are `None` for a record that was cut short rather than failing its checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CorruptRecord {
+    pub offset: u64,
+    pub expected: Option<u64>,
+    pub actual: Option<u64>,
+}
+
+/// The bytes at the end of a segment that `load` would discard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TornTail {
+    pub offset: u64,
+    pub bytes: u64,
+}
+
+/// The newest record seen for a key
+struct Latest {
+    /// Size of the record, header included
+    len: u64,
+    live: bool,
+}
+
+pub(crate) fn check(dir: &Path) -> Result<CheckReport> {
+    let (found, leftovers) = segment::find(dir)?;
+    let mut report = CheckReport {
+        dir: dir.to_path_buf(),
+        leftovers,
+        ..CheckReport::default()
+    };
+
+    let mut keys = BTreeMap::new();
+    let newest = found.keys().next_back().copied();
+    for (&id, &first) in &found {
+        let path = dir.join(Segment::file_name(first, id));
+        let newest = Some(id) == newest;
+        let segment = check_segment(path, first, id, newest, &mut keys, &mut report)?;
+        report.total_bytes += segment.len;
+        report.corrupt += segment.corrupt.len() as u64;
+        report.segments.push(segment);
+    }
+
+    for latest in keys.values().filter(|latest| latest.live) {
+        report.live_keys += 1;
+        report.live_bytes += latest.len;
+    }
+    if report.live_bytes > 0 {
+        report.space_amplification = Some(report.total_bytes as f64 / report.live_bytes as f64);
+    }
+
+    Ok(report)
+}
+
+/// Checks the segment at `path`, recording the newest record for each key in `keys`
+///
+/// A segment from a newer build fails the whole check with
+/// `ActionKvError::UnsupportedVersion`, as there is no telling what its records look like.
+fn check_segment(
+    path: PathBuf,
+    first: u32,
+    id: u32,
+    newest: bool,
+    keys: &mut BTreeMap<ByteString, Latest>,
+    totals: &mut CheckReport,
+) -> Result<SegmentReport> {
+    let mut f = File::open(&path)?;
+    let len = f.metadata()?.len();
+    let mut report = SegmentReport {
+        id,
+        first,
+        path,
+        len,
+        ..SegmentReport::default()
+    };
+
+    let header = match Header::read(&mut f) {
+        Ok(header) => header,
+        Err(err @ ActionKvError::UnsupportedVersion { .. }) => return Err(err),
+        Err(err) => {
+            report.header_error = Some(err.to_string());
+            return Ok(report);
+        }
+    };
+
+    match header {
+        Some(header) if header.version == FORMAT_VERSION => {
+            report.format_version = Some(header.version);
+            let mut f = BufReader::new(f);
+            f.seek(SeekFrom::Start(HEADER_LEN))?;
+            scan(
+                &mut f,
+                len,
+                header.checksum,
+                newest,
+                keys,
+                &mut report,
+                totals,
+            )?;
+        }
+        header => {
+            let (version, checksum) = header.map_or((0, ChecksumKind::Crc32), |header| {
+                (header.version, header.checksum)
+            });
+            report.format_version = Some(version);
+            report.outdated = true;
+
+            f.seek(SeekFrom::Start(header_len(version)))?;
+            let upgraded = match upgrade::upgraded(f, version, checksum, newest) {
+                Ok(upgraded) => upgraded,
+                Err(ActionKvError::Truncated { offset }) => {
+                    report.corrupt.push(CorruptRecord {
+                        offset,
+                        expected: None,
+                        actual: None,
+                    });
+                    return Ok(report);
+                }
+                Err(err) => return Err(err),
+            };
+            let len = upgraded.len() as u64;
+            let mut f = Cursor::new(upgraded);
+            f.seek(SeekFrom::Start(HEADER_LEN))?;
+            scan(&mut f, len, checksum, newest, keys, &mut report, totals)?;
+        }
+    }
+
+    totals.records += report.records;
+    totals.values += report.values;
+    totals.tombstones += report.tombstones;
+    totals.batches += report.batches;
+
+    Ok(report)
+}
+
+/// Reads every record in a segment `len` bytes long from `f`, positioned at its first record
+///
+/// Damaged records are dealt with as `RecoveryPolicy::Skip` would, carrying on past them
+/// for as long as anything intact follows.
+fn scan<R: Read + Seek>(
+    f: &mut R,
+    len: u64,
+    checksum: ChecksumKind,
+    newest: bool,
+    keys: &mut BTreeMap<ByteString, Latest>,
+    report: &mut SegmentReport,
+    totals: &mut CheckReport,
+) -> Result<()> {
+    loop {
+        let pos = f.stream_position()?;
+
+        let record = ActionKV::read_record(f, pos, len, checksum, Decryptor::default(), false);
+        let damaged = match record {
+            Ok(record) => {
+                let end = f.stream_position()?;
+                tally(record, pos, end, keys, report, totals);
+                continue;
+            }
+            Err(err) if err.is_eof() => break,
+            Err(ActionKvError::Truncated { .. }) if newest => {
+                report.torn_tail = Some(TornTail {
+                    offset: pos,
+                    bytes: len - pos,
+                });
+                break;
+            }
+            Err(ActionKvError::Truncated { offset }) => CorruptRecord {
+                offset,
+                expected: None,
+                actual: None,
+            },
+            Err(ActionKvError::Corruption {
+                offset,
+                expected,
+                actual,
+            }) => CorruptRecord {
+                offset,
+                expected: Some(expected),
+                actual: Some(actual),
+            },
+            Err(err) => return Err(err),
+        };
+
+        report.corrupt.push(damaged);
+        match ActionKV::skip_damaged(f, len, pos)? {
+            Some(next) => f.seek(SeekFrom::Start(next))?,
+            None => break,
+        };
+    }
+
+    Ok(())
+}
+
+/// Counts the values and tombstones in `record`, which spans `pos` to `end`
+fn tally(
+    record: Record,
+    pos: u64,
+    end: u64,
+    keys: &mut BTreeMap<ByteString, Latest>,
+    report: &mut SegmentReport,
+    totals: &mut CheckReport,
+) {
+    if let Record::Batch(..) = record {
+        report.batches += 1;
+    }
+    let mut entries = Vec::new();
+    record.for_each(pos, |offset, record| entries.push((offset, record)));
+
+    let mut ends = entries.iter().skip(1).map(|(offset, _)| *offset);
+    for (offset, record) in entries.iter() {
+        let record_len = ends.next().unwrap_or(end) - offset;
+        report.records += 1;
+
+        let (key, live) = match record {
+            Record::Value(kv) => {
+                report.values += 1;
+                if kv.is_expired() {
+                    totals.expired += 1;
+                }
+                (&kv.key, !kv.is_expired())
+            }
+            Record::Tombstone(key, _) => {
+                report.tombstones += 1;
+                (key, false)
+            }
+            Record::Batch(..) => unreachable!("batches are unpacked by for_each"),
+        };
+
+        let latest = Latest {
+            len: record_len,
+            live,
+        };
+        if keys.insert(key.clone(), latest).is_some() {
+            totals.duplicates += 1;
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crc::{Crc, Digest, CRC_32_ISCSI};
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::CRC32;
+
+static CRC32C: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+/// Algorithm used to checksum records, chosen per store with `Options::checksum`
+///
+/// Each segment records the algorithm it was written with in its header, so changing it only
+/// affects segments started afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumKind {
+    /// CRC-32/CKSUM, the algorithm used before checksums became configurable
+    #[default]
+    Crc32,
+    /// CRC-32C (Castagnoli), which detects more error patterns than `Crc32`
+    Crc32c,
+    /// 64-bit xxHash, which is both faster and less likely to miss a corrupted record
+    XxHash64,
+}
+
+impl ChecksumKind {
+    /// Identifier stored in segment headers
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            ChecksumKind::Crc32 => 0,
+            ChecksumKind::Crc32c => 1,
+            ChecksumKind::XxHash64 => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(ChecksumKind::Crc32),
+            1 => Some(ChecksumKind::Crc32c),
+            2 => Some(ChecksumKind::XxHash64),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            ChecksumKind::Crc32 => Hasher::Crc32(CRC32.digest()),
+            ChecksumKind::Crc32c => Hasher::Crc32(CRC32C.digest()),
+            ChecksumKind::XxHash64 => Hasher::XxHash64(Xxh64::new(0)),
+        }
+    }
+}
+
+/// Running checksum of a record, fed a piece at a time
+#[derive(Clone)]
+pub(crate) enum Hasher {
+    Crc32(Digest<'static, u32>),
+    XxHash64(Xxh64),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Crc32(digest) => digest.update(bytes),
+            Hasher::XxHash64(hasher) => hasher.update(bytes),
+        }
+    }
+
+    pub(crate) fn finish(self) -> u64 {
+        match self {
+            Hasher::Crc32(digest) => digest.finalize() as u64,
+            Hasher::XxHash64(hasher) => hasher.digest(),
+        }
+    }
+}
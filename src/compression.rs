@@ -0,0 +1,61 @@
+use std::borrow::Cow;
+
+use crate::{ByteStr, ByteString};
+
+/// Bits of a record's flags byte that say how its value is compressed
+pub(crate) const COMPRESSION_MASK: u8 = 0b11;
+const FLAG_LZ4: u8 = 1;
+const FLAG_ZSTD: u8 = 2;
+
+/// How values are compressed before they are written to the log
+///
+/// Set for a whole store with `Options::compression`, or for a single write with
+/// `ActionKV::insert_compressed`. Every record says how its own value was compressed, so
+/// records written with different settings can sit side by side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// LZ4, which is very fast but compresses less than zstd
+    Lz4,
+    /// zstd at the given level, from 1 (fastest) to 22 (smallest); 0 picks zstd's default
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Compresses `value`, returning the record flags to write alongside the result
+    ///
+    /// A value that doesn't get any smaller is left as it is.
+    pub(crate) fn compress(self, value: &ByteStr) -> std::io::Result<(u8, Cow<'_, ByteStr>)> {
+        let (flag, compressed) = match self {
+            Compression::None => return Ok((0, Cow::Borrowed(value))),
+            Compression::Lz4 => (FLAG_LZ4, lz4_flex::compress_prepend_size(value)),
+            Compression::Zstd(level) => (FLAG_ZSTD, zstd::bulk::compress(value, level)?),
+        };
+
+        if compressed.len() < value.len() {
+            Ok((flag, Cow::Owned(compressed)))
+        } else {
+            Ok((0, Cow::Borrowed(value)))
+        }
+    }
+}
+
+/// Whether a record with `flags` holds a compressed value
+pub(crate) fn is_compressed(flags: u8) -> bool {
+    flags & COMPRESSION_MASK != 0
+}
+
+/// Undoes the compression described by `flags` on a value read from the log
+pub(crate) fn decompress(flags: u8, stored: ByteString) -> std::io::Result<ByteString> {
+    let invalid = |err: String| std::io::Error::new(std::io::ErrorKind::InvalidData, err);
+
+    match flags & COMPRESSION_MASK {
+        0 => Ok(stored),
+        FLAG_LZ4 => lz4_flex::decompress_size_prepended(&stored)
+            .map_err(|err| invalid(format!("invalid lz4 data: {}", err))),
+        FLAG_ZSTD => zstd::stream::decode_all(&stored[..])
+            .map_err(|err| invalid(format!("invalid zstd data: {}", err))),
+        other => Err(invalid(format!("unknown compression {}", other))),
+    }
+}
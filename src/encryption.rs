@@ -0,0 +1,149 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+use crate::record::RecordHeader;
+use crate::{ActionKvError, ByteStr, ByteString, Options, Result};
+
+/// Bit of a record's flags byte set when its value is encrypted
+pub(crate) const FLAG_ENCRYPTED: u8 = 0b100;
+
+/// Bit of a record's flags byte set alongside `FLAG_ENCRYPTED` when the rest of the record's
+/// header, bar the lengths and checksums, is authenticated along with its key
+///
+/// Values encrypted before it was added authenticate only their key, and are still read back
+/// that way. Clearing or setting the bit makes a value fail to decrypt like any other change.
+pub(crate) const FLAG_AUTHENTICATED: u8 = 0b1000;
+
+/// Size of the nonce stored in front of every encrypted value
+const NONCE_LEN: usize = 12;
+
+/// 256-bit key used to encrypt values with ChaCha20-Poly1305, set with
+/// `Options::encryption_key`
+///
+/// The key is kept out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// How values are decrypted as they are read back, as set up by `Options`
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Decryptor<'a> {
+    encryption_key: Option<&'a EncryptionKey>,
+    /// Whether values stored in plaintext are refused; see `Options::require_encryption`
+    require: bool,
+}
+
+impl<'a> Decryptor<'a> {
+    pub(crate) fn new(options: &'a Options) -> Self {
+        let encryption_key = options.encryption_key.as_ref();
+        Self {
+            encryption_key,
+            require: options.require_encryption && encryption_key.is_some(),
+        }
+    }
+
+    /// Fails if the value in the record at `offset`, stored in plaintext, isn't to be trusted
+    pub(crate) fn check_plaintext(&self, offset: u64) -> Result<()> {
+        if self.require {
+            return Err(ActionKvError::Decryption { offset });
+        }
+
+        Ok(())
+    }
+
+    /// Decrypts a value written by `encrypt` for the record at `offset`, passing plaintext
+    /// values through if they are allowed
+    pub(crate) fn decrypt(
+        &self,
+        offset: u64,
+        header: &RecordHeader,
+        key: &ByteStr,
+        stored: ByteString,
+    ) -> Result<ByteString> {
+        if header.flags & FLAG_ENCRYPTED == 0 {
+            self.check_plaintext(offset)?;
+            return Ok(stored);
+        }
+
+        let encryption_key = match self.encryption_key {
+            Some(encryption_key) => encryption_key,
+            None => return Err(ActionKvError::Decryption { offset }),
+        };
+        if stored.len() < NONCE_LEN {
+            return Err(ActionKvError::Decryption { offset });
+        }
+
+        let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &associated_data(header, key),
+        };
+        encryption_key
+            .cipher()
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| ActionKvError::Decryption { offset })
+    }
+}
+
+/// Encrypts the value stored under `key`, returning `<nonce><ciphertext><tag>`
+///
+/// Every value gets a fresh random nonce. `header` must already have `FLAG_ENCRYPTED` and
+/// `FLAG_AUTHENTICATED` set, and its flags, expiry time, sequence number and timestamp are
+/// authenticated along with the key, so a value can't be moved to another key or record, or
+/// have its compression flags or expiry changed, without failing to decrypt.
+pub(crate) fn encrypt(
+    encryption_key: &EncryptionKey,
+    header: &RecordHeader,
+    key: &ByteStr,
+    value: &ByteStr,
+) -> std::io::Result<ByteString> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: value,
+        aad: &associated_data(header, key),
+    };
+    let ciphertext = encryption_key
+        .cipher()
+        .encrypt(&nonce, payload)
+        .map_err(|_| std::io::Error::other("failed to encrypt value"))?;
+
+    let mut stored = ByteString::with_capacity(NONCE_LEN + ciphertext.len());
+    stored.extend_from_slice(&nonce);
+    stored.extend_from_slice(&ciphertext);
+    Ok(stored)
+}
+
+/// Data authenticated along with the value of the record with `header`: its key, followed
+/// by its flags, expiry time, sequence number and timestamp unless it predates
+/// `FLAG_AUTHENTICATED`
+fn associated_data<'k>(header: &RecordHeader, key: &'k ByteStr) -> Cow<'k, ByteStr> {
+    if header.flags & FLAG_AUTHENTICATED == 0 {
+        return Cow::Borrowed(key);
+    }
+
+    let mut aad = ByteString::with_capacity(key.len() + 25);
+    aad.extend_from_slice(key);
+    aad.push(header.flags);
+    aad.extend_from_slice(&header.expires_at.to_le_bytes());
+    aad.extend_from_slice(&header.seq.to_le_bytes());
+    aad.extend_from_slice(&header.timestamp.to_le_bytes());
+    Cow::Owned(aad)
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::ByteString;
+
+/// Errors returned by `ActionKV` operations
+#[derive(Debug)]
+pub enum ActionKvError {
+    /// An error from the underlying file
+    Io(std::io::Error),
+    /// The record at `offset` failed its checksum: `expected` is the checksum stored in the
+    /// record header and `actual` the one computed from the data read back
+    Corruption {
+        offset: u64,
+        expected: u64,
+        actual: u64,
+    },
+    /// The log ends part way through the record at `offset`, usually because a write was
+    /// interrupted
+    Truncated { offset: u64 },
+    /// A key longer than `limit` bytes, set by `Options::max_key_size`, was written
+    KeyTooLarge { len: u64, limit: u64 },
+    /// A value longer than `limit` bytes, set by `Options::max_value_size`, was written
+    ValueTooLarge { len: u64, limit: u64 },
+    /// A segment was written in a newer format than this build understands
+    UnsupportedVersion { version: u16 },
+    /// The encrypted value in the record at `offset` couldn't be decrypted: no key or the
+    /// wrong key was given in `Options::encryption_key`, or the record has been tampered with.
+    /// Also returned for a value stored in plaintext when `Options::require_encryption` is set.
+    Decryption { offset: u64 },
+    /// A conditional write to `key` was refused because its value wasn't the one expected
+    Conflict { key: ByteString },
+    /// The store is already open elsewhere, either for writing or, when opening it for
+    /// writing, at all
+    Locked,
+    /// A write was attempted on a store opened with `Options::read_only`, or the store can't
+    /// be opened read-only because it first needs upgrading to the current format
+    ReadOnly,
+    /// A write, or another operation that relies on the index, was attempted before
+    /// `ActionKV::load` was called to build it
+    NotLoaded,
+}
+
+pub type Result<T> = std::result::Result<T, ActionKvError>;
+
+impl ActionKvError {
+    /// Whether the error was caused by reaching the end of the log
+    pub(crate) fn is_eof(&self) -> bool {
+        matches!(self, ActionKvError::Io(err) if err.kind() == std::io::ErrorKind::UnexpectedEof)
+    }
+}
+
+impl fmt::Display for ActionKvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionKvError::Io(err) => write!(f, "i/o error: {}", err),
+            ActionKvError::Corruption {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "data corruption encountered at offset {}: ({:08x} != {:08x})",
+                offset, actual, expected
+            ),
+            ActionKvError::Truncated { offset } => {
+                write!(f, "incomplete record at offset {}", offset)
+            }
+            ActionKvError::KeyTooLarge { len, limit } => {
+                write!(
+                    f,
+                    "key of {} bytes exceeds the limit of {} bytes",
+                    len, limit
+                )
+            }
+            ActionKvError::ValueTooLarge { len, limit } => {
+                write!(
+                    f,
+                    "value of {} bytes exceeds the limit of {} bytes",
+                    len, limit
+                )
+            }
+            ActionKvError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported format version {} (newest supported is {})",
+                version,
+                crate::FORMAT_VERSION
+            ),
+            ActionKvError::Decryption { offset } => write!(
+                f,
+                "failed to decrypt record at offset {}: wrong key or tampered data",
+                offset
+            ),
+            ActionKvError::Conflict { key } => write!(
+                f,
+                "conditional write to key {:?} conflicted with its current value",
+                String::from_utf8_lossy(key)
+            ),
+            ActionKvError::Locked => write!(f, "store is locked by another handle"),
+            ActionKvError::ReadOnly => write!(f, "store is opened read-only"),
+            ActionKvError::NotLoaded => write!(f, "store has not been loaded"),
+        }
+    }
+}
+
+impl std::error::Error for ActionKvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ActionKvError::Io(err) => Some(err),
+            ActionKvError::Corruption { .. }
+            | ActionKvError::Truncated { .. }
+            | ActionKvError::KeyTooLarge { .. }
+            | ActionKvError::ValueTooLarge { .. }
+            | ActionKvError::UnsupportedVersion { .. }
+            | ActionKvError::Decryption { .. }
+            | ActionKvError::Conflict { .. }
+            | ActionKvError::Locked
+            | ActionKvError::ReadOnly
+            | ActionKvError::NotLoaded => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ActionKvError {
+    fn from(err: std::io::Error) -> Self {
+        ActionKvError::Io(err)
+    }
+}
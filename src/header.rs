@@ -0,0 +1,135 @@
+//! Header written at the start of every segment file
+//!
+//! The header is laid out as `<magic><version><created_at><checksum_kind><checksum>`: the
+//! bytes `ACTIONKV`, the format version as a `u16`, the time the file was created in seconds
+//! since the Unix epoch as a `u64`, the `ChecksumKind` used for the segment's records as a
+//! byte, and a CRC32 of everything before it, all little-endian. Records follow straight
+//! after it. Headers written before version 3 have no checksum kind; those segments used
+//! `ChecksumKind::Crc32`.
+//!
+//! Files written before the header was introduced start with their first record. They are
+//! treated as format version 0. Segments in an older format are upgraded in place when the
+//! store is opened; see `upgrade`.
+
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{ActionKvError, ByteString, ChecksumKind, Result, CRC32};
+
+const MAGIC: &[u8; 8] = b"ACTIONKV";
+
+/// Version of the on-disk format written by this build, and the newest one it can read
+pub const FORMAT_VERSION: u16 = 7;
+
+/// Size of the current header; the first record in a segment starts at this offset
+pub(crate) const HEADER_LEN: u64 = 23;
+
+/// Size of the header written by format `version`
+pub(crate) fn header_len(version: u16) -> u64 {
+    match version {
+        0 => 0,
+        1 | 2 => 22,
+        _ => HEADER_LEN,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub(crate) version: u16,
+    /// Seconds since the Unix epoch
+    pub(crate) created_at: u64,
+    pub(crate) checksum: ChecksumKind,
+}
+
+impl Header {
+    /// A header in the current format for a file created now
+    pub(crate) fn new(checksum: ChecksumKind) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            version: FORMAT_VERSION,
+            created_at,
+            checksum,
+        }
+    }
+
+    pub(crate) fn created_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.created_at)
+    }
+
+    pub(crate) fn encode(&self) -> ByteString {
+        let mut buf = ByteString::with_capacity(HEADER_LEN as usize);
+        buf.extend_from_slice(MAGIC);
+        buf.write_u16::<LittleEndian>(self.version).unwrap();
+        buf.write_u64::<LittleEndian>(self.created_at).unwrap();
+        buf.push(self.checksum.id());
+        let checksum = CRC32.checksum(&buf);
+        buf.write_u32::<LittleEndian>(checksum).unwrap();
+
+        buf
+    }
+
+    /// Reads the header from the start of a segment
+    ///
+    /// Returns `None` for a file that doesn't start with the magic bytes, i.e. one written
+    /// before headers existed. The version is checked before anything else so that a file
+    /// from a newer build is reported as such even if its header has since grown.
+    pub(crate) fn read<R: Read>(f: &mut R) -> Result<Option<Header>> {
+        let mut buf = ByteString::with_capacity(HEADER_LEN as usize);
+        f.take(HEADER_LEN).read_to_end(&mut buf)?;
+        if !buf.starts_with(MAGIC) {
+            return Ok(None);
+        }
+
+        let mut fields = &buf[MAGIC.len()..];
+        let version = match fields.read_u16::<LittleEndian>() {
+            Ok(version) => version,
+            Err(_) => return Err(ActionKvError::Truncated { offset: 0 }),
+        };
+        if version > FORMAT_VERSION {
+            return Err(ActionKvError::UnsupportedVersion { version });
+        }
+        // Version 0 stands for files without a header, so a header claiming it must have a
+        // damaged version; its checksum is checked as though it were in the current layout
+        let len = match version {
+            0 => HEADER_LEN,
+            version => header_len(version),
+        } as usize;
+        if buf.len() < len {
+            return Err(ActionKvError::Truncated { offset: 0 });
+        }
+
+        let created_at = fields.read_u64::<LittleEndian>()?;
+        let checksum_id = match version {
+            1 | 2 => ChecksumKind::Crc32.id(),
+            _ => fields.read_u8()?,
+        };
+        let saved_checksum = fields.read_u32::<LittleEndian>()?;
+        let checksum = CRC32.checksum(&buf[..len - 4]);
+        if checksum != saved_checksum || version == 0 {
+            return Err(ActionKvError::Corruption {
+                offset: 0,
+                expected: saved_checksum as u64,
+                actual: checksum as u64,
+            });
+        }
+
+        let checksum = ChecksumKind::from_id(checksum_id).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown checksum algorithm {}", checksum_id),
+            )
+        })?;
+
+        Ok(Some(Header {
+            version,
+            created_at,
+            checksum,
+        }))
+    }
+}
@@ -0,0 +1,157 @@
+//! Sidecar hint file used to rebuild the index without scanning every value in the log
+//!
+//! The hint holds a serialized copy of the index together with the segments it was taken
+//! from and their lengths, plus a checksum of the bytes at the end of the newest of them.
+//! `load` only trusts a hint whose segments are all still in place; records appended since
+//! the hint was written are picked up by scanning from where it left off.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::segment::Segment;
+use crate::{ByteString, Index, Position, CRC32};
+
+const HINT_FILE_NAME: &str = "index.hint";
+
+/// Number of bytes at the end of the covered log that are checksummed to detect a stale hint
+const TAIL_LEN: u64 = 4096;
+
+#[derive(Serialize, Deserialize)]
+struct Hint<'a> {
+    /// `(first, id, len)` for every segment the index was built from, oldest first
+    segments: Vec<(u32, u32, u64)>,
+    tail_checksum: u32,
+    /// Highest sequence number in the covered segments
+    last_seq: u64,
+    index: Cow<'a, Index>,
+}
+
+fn hint_path(dir: &Path) -> PathBuf {
+    dir.join(HINT_FILE_NAME)
+}
+
+/// Checksums the last `TAIL_LEN` bytes of the first `data_len` bytes of `segment`
+fn tail_checksum(segment: &Segment, data_len: u64) -> std::io::Result<u32> {
+    let start = data_len.saturating_sub(TAIL_LEN);
+    let mut tail = ByteString::with_capacity((data_len - start) as usize);
+    let mut f = segment.reader();
+    f.seek(SeekFrom::Start(start))?;
+    f.take(data_len - start).read_to_end(&mut tail)?;
+
+    Ok(CRC32.checksum(&tail))
+}
+
+/// Writes a hint for `index`, which must describe the full contents of `segments`, whose
+/// latest write had sequence number `last_seq`
+///
+/// The hint is written to a temporary file and renamed into place so that a reader never
+/// sees a partially written hint.
+pub(crate) fn write(
+    dir: &Path,
+    segments: &BTreeMap<u32, Segment>,
+    index: &Index,
+    last_seq: u64,
+) -> std::io::Result<()> {
+    let covered = segments
+        .values()
+        .map(|segment| (segment.first, segment.id, segment.len))
+        .collect();
+    let tail_checksum = match segments.values().next_back() {
+        Some(segment) => tail_checksum(segment, segment.len)?,
+        None => 0,
+    };
+
+    let hint = Hint {
+        segments: covered,
+        tail_checksum,
+        last_seq,
+        index: Cow::Borrowed(index),
+    };
+    let encoded = bincode::serialize(&hint)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+
+    let hint_path = hint_path(dir);
+    let tmp_path = hint_path.with_extension("hint.tmp");
+
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(&CRC32.checksum(&encoded).to_le_bytes())?;
+    tmp.write_all(&encoded)?;
+    tmp.sync_all()?;
+    drop(tmp);
+
+    std::fs::rename(&tmp_path, &hint_path)
+}
+
+/// Deletes the hint for the store in `dir`, if it has one
+pub(crate) fn remove(dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(hint_path(dir)) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Reads the hint for the store in `dir`
+///
+/// Returns the index along with the position just past the last record it covers and the
+/// highest sequence number among those records, or `None` when there is no hint, it is
+/// damaged, or the segments on disk no longer match the ones it was taken from.
+pub(crate) fn read(
+    dir: &Path,
+    segments: &BTreeMap<u32, Segment>,
+) -> std::io::Result<Option<(Index, Position, u64)>> {
+    let contents = match std::fs::read(hint_path(dir)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if contents.len() < 4 {
+        return Ok(None);
+    }
+
+    let (saved_checksum, encoded) = contents.split_at(4);
+    let saved_checksum = u32::from_le_bytes(saved_checksum.try_into().unwrap());
+    if CRC32.checksum(encoded) != saved_checksum {
+        return Ok(None);
+    }
+
+    let hint: Hint = match bincode::deserialize(encoded) {
+        Ok(hint) => hint,
+        Err(_) => return Ok(None),
+    };
+    let (last_first, last_id, last_len) = match hint.segments.last() {
+        Some(&last) => last,
+        None => return Ok(None),
+    };
+
+    // Every segment up to the newest covered one must be exactly as the hint saw it, apart
+    // from the newest which may have been appended to since
+    if segments.range(..=last_id).count() != hint.segments.len() {
+        return Ok(None);
+    }
+    for &(first, id, len) in &hint.segments {
+        let matches = match segments.get(&id) {
+            Some(segment) if id == last_id => segment.first == first && segment.len >= len,
+            Some(segment) => segment.first == first && segment.len == len,
+            None => false,
+        };
+        if !matches {
+            return Ok(None);
+        }
+    }
+
+    let last = &segments[&last_id];
+    if last.first != last_first || tail_checksum(last, last_len)? != hint.tail_checksum {
+        return Ok(None);
+    }
+
+    let end = Position {
+        segment: last_id,
+        offset: last_len,
+    };
+    Ok(Some((hint.index.into_owned(), end, hint.last_seq)))
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, RangeBounds};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ByteStr, ByteString, Position};
+
+/// Which data structure backs a store's index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexKind {
+    /// A hash map: the fastest point lookups, but range and prefix scans have to sort the
+    /// keys they match
+    #[default]
+    Hashed,
+    /// A B-tree kept in key order, so range and prefix scans only visit matching keys
+    Ordered,
+}
+
+/// Maps every live key in the store to the position of its latest value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Index {
+    Hashed(HashMap<ByteString, Position>),
+    Ordered(BTreeMap<ByteString, Position>),
+}
+
+impl Index {
+    pub fn new(kind: IndexKind) -> Self {
+        match kind {
+            IndexKind::Hashed => Index::Hashed(HashMap::new()),
+            IndexKind::Ordered => Index::Ordered(BTreeMap::new()),
+        }
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        match self {
+            Index::Hashed(_) => IndexKind::Hashed,
+            Index::Ordered(_) => IndexKind::Ordered,
+        }
+    }
+
+    /// Converts the index to `kind`, keeping its entries
+    pub fn into_kind(self, kind: IndexKind) -> Self {
+        match (self, kind) {
+            (Index::Hashed(map), IndexKind::Ordered) => Index::Ordered(map.into_iter().collect()),
+            (Index::Ordered(map), IndexKind::Hashed) => Index::Hashed(map.into_iter().collect()),
+            (index, _) => index,
+        }
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Option<&Position> {
+        match self {
+            Index::Hashed(map) => map.get(key),
+            Index::Ordered(map) => map.get(key),
+        }
+    }
+
+    pub fn contains_key(&self, key: &ByteStr) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: ByteString, position: Position) -> Option<Position> {
+        match self {
+            Index::Hashed(map) => map.insert(key, position),
+            Index::Ordered(map) => map.insert(key, position),
+        }
+    }
+
+    pub fn remove(&mut self, key: &ByteStr) -> Option<Position> {
+        match self {
+            Index::Hashed(map) => map.remove(key),
+            Index::Ordered(map) => map.remove(key),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Index::Hashed(map) => map.len(),
+            Index::Ordered(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over every entry; in key order for an ordered index, arbitrary otherwise
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&ByteString, &Position)> + '_> {
+        match self {
+            Index::Hashed(map) => Box::new(map.iter()),
+            Index::Ordered(map) => Box::new(map.iter()),
+        }
+    }
+
+    /// Positions of the keys within `range`, in key order
+    pub(crate) fn range_positions<'k, R: RangeBounds<&'k ByteStr>>(
+        &self,
+        range: R,
+    ) -> Vec<Position> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        match self {
+            Index::Hashed(map) => sorted_matches(map, |key| bounds.contains(key)),
+            Index::Ordered(_) if is_empty_range(bounds) => Vec::new(),
+            Index::Ordered(map) => map
+                .range::<ByteStr, _>(bounds)
+                .map(|(_, pos)| *pos)
+                .collect(),
+        }
+    }
+
+    /// Positions of the keys starting with `prefix`, in key order
+    pub(crate) fn prefix_positions(&self, prefix: &ByteStr) -> Vec<Position> {
+        match self {
+            Index::Hashed(map) => sorted_matches(map, |key| key.starts_with(prefix)),
+            Index::Ordered(map) => map
+                .range::<ByteStr, _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .map(|(_, pos)| *pos)
+                .collect(),
+        }
+    }
+}
+
+/// Positions of the keys in `map` accepted by `matches`, in key order
+fn sorted_matches<F: Fn(&ByteStr) -> bool>(
+    map: &HashMap<ByteString, Position>,
+    matches: F,
+) -> Vec<Position> {
+    let mut matched: Vec<_> = map.iter().filter(|(key, _)| matches(key)).collect();
+    matched.sort_unstable_by_key(|(key, _)| *key);
+    matched.into_iter().map(|(_, pos)| *pos).collect()
+}
+
+/// Whether `bounds` can't contain anything, which `BTreeMap::range` panics on
+fn is_empty_range(bounds: (Bound<&ByteStr>, Bound<&ByteStr>)) -> bool {
+    match bounds {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    }
+}
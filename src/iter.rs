@@ -0,0 +1,212 @@
+use std::collections::{btree_map, VecDeque};
+use std::io::{BufReader, Seek, SeekFrom};
+use std::time::SystemTime;
+
+use crate::encryption::Decryptor;
+use crate::record;
+use crate::segment::{PositionalReader, Segment};
+use crate::{
+    ActionKV, ActionKvError, ByteString, KeyValuePair, Position, Record, RecoveryPolicy, Result,
+};
+
+/// Iterator over entries of the store, reading each value from the log as it is reached
+///
+/// Entries whose TTL has run out are skipped.
+pub struct Entries<'a> {
+    store: &'a ActionKV,
+    positions: std::vec::IntoIter<Position>,
+}
+
+impl<'a> Entries<'a> {
+    pub(crate) fn new(store: &'a ActionKV, positions: Vec<Position>) -> Self {
+        Self {
+            store,
+            positions: positions.into_iter(),
+        }
+    }
+}
+
+impl Iterator for Entries<'_> {
+    type Item = Result<KeyValuePair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let position = self.positions.next()?;
+            match self.store.get_at(position) {
+                Ok(kv) if kv.is_expired() => continue,
+                result => return Some(result),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.positions.len()))
+    }
+}
+
+/// A record read back from the log by `ActionKV::iter_log`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub position: Position,
+    pub key: ByteString,
+    /// The value written, or `None` if the record is a tombstone
+    pub value: Option<ByteString>,
+    /// Sequence number of the write; see `KeyValuePair::seq`
+    pub seq: u64,
+    /// When the record was written, if known
+    pub timestamp: Option<SystemTime>,
+}
+
+/// Iterator over every record in a run of segments, in the order they were written
+///
+/// Overwritten values and tombstones are included, and batches are unpacked into the
+/// records they hold. A torn write at the end of the active segment ends the iteration, as
+/// it does `load`. Any other damaged record, including one cut short in a sealed segment,
+/// moves on to the next segment unless the store's `RecoveryPolicy` says to skip it or fail.
+/// After an error the iterator is exhausted.
+pub struct LogEntries<'a> {
+    recovery: RecoveryPolicy,
+    decryptor: Decryptor<'a>,
+    /// Whether values are read back; without them every value comes back empty, which is
+    /// all that is needed to pick out tombstones
+    values: bool,
+    /// Id of the segment being appended to, the only one that can end in a torn write
+    active: u32,
+    segments: btree_map::Range<'a, u32, Segment>,
+    current: Option<(&'a Segment, BufReader<PositionalReader<'a>>)>,
+    pending: VecDeque<LogEntry>,
+    done: bool,
+}
+
+impl<'a> LogEntries<'a> {
+    pub(crate) fn new(
+        segments: btree_map::Range<'a, u32, Segment>,
+        recovery: RecoveryPolicy,
+        decryptor: Decryptor<'a>,
+        values: bool,
+        active: u32,
+    ) -> Self {
+        Self {
+            recovery,
+            decryptor,
+            values,
+            active,
+            segments,
+            current: None,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl Iterator for LogEntries<'_> {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Some(Ok(entry));
+            }
+            if self.done {
+                return None;
+            }
+
+            let (segment, f) = match &mut self.current {
+                Some((segment, f)) => (*segment, f),
+                None => match self.segments.next() {
+                    Some((_, segment)) => {
+                        let f = BufReader::new(segment.reader());
+                        let (_, f) = self.current.insert((segment, f));
+                        (segment, f)
+                    }
+                    None => {
+                        self.done = true;
+                        return None;
+                    }
+                },
+            };
+
+            let pos = match f.stream_position() {
+                Ok(pos) => pos,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            };
+
+            let checksum = segment.header.checksum;
+            let decryptor = self.decryptor;
+            let record =
+                ActionKV::read_record(f, pos, segment.len, checksum, decryptor, self.values);
+            let record = match record {
+                Ok(record) => record,
+                Err(err) if err.is_eof() => {
+                    self.current = None;
+                    continue;
+                }
+                Err(ActionKvError::Truncated { .. }) if segment.id == self.active => {
+                    self.current = None;
+                    continue;
+                }
+                Err(err @ (ActionKvError::Corruption { .. } | ActionKvError::Truncated { .. })) => {
+                    match self.recovery {
+                        RecoveryPolicy::Fail => {
+                            self.done = true;
+                            return Some(Err(err));
+                        }
+                        RecoveryPolicy::Skip => {
+                            let next = ActionKV::skip_damaged(f, segment.len, pos);
+                            match next {
+                                Ok(Some(next)) => {
+                                    if let Err(err) = f.seek(SeekFrom::Start(next)) {
+                                        self.done = true;
+                                        return Some(Err(err.into()));
+                                    }
+                                }
+                                Ok(None) => self.current = None,
+                                Err(err) => {
+                                    self.done = true;
+                                    return Some(Err(err));
+                                }
+                            }
+                            continue;
+                        }
+                        RecoveryPolicy::Truncate => {
+                            self.current = None;
+                            continue;
+                        }
+                    }
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            };
+
+            record.for_each(pos, |offset, record| {
+                let position = Position {
+                    segment: segment.id,
+                    offset,
+                };
+                let entry = match record {
+                    Record::Value(kv) => LogEntry {
+                        position,
+                        key: kv.key,
+                        value: Some(kv.value),
+                        seq: kv.seq,
+                        timestamp: kv.timestamp,
+                    },
+                    Record::Tombstone(key, header) => LogEntry {
+                        position,
+                        key,
+                        value: None,
+                        seq: header.seq,
+                        timestamp: record::to_system_time(header.timestamp),
+                    },
+                    Record::Batch(..) => unreachable!("batches are unpacked by for_each"),
+                };
+                self.pending.push_back(entry);
+            });
+        }
+    }
+}
@@ -1,12 +1,60 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::ops::{RangeBounds, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 use crc::{Crc, CRC_32_CKSUM};
 use serde::{Deserialize, Serialize};
 
+mod batch;
+mod check;
+mod checksum;
+mod compression;
+mod encryption;
+mod error;
+mod header;
+mod hint;
+mod index;
+mod iter;
+mod lock;
+mod options;
+mod reader;
+mod record;
+mod repair;
+mod segment;
+mod shared;
+mod snapshot;
+mod transaction;
+mod upgrade;
+
+pub use batch::WriteBatch;
+pub use check::{CheckReport, CorruptRecord, SegmentReport, TornTail};
+pub use checksum::ChecksumKind;
+pub use compression::Compression;
+pub use encryption::EncryptionKey;
+pub use error::{ActionKvError, Result};
+pub use header::FORMAT_VERSION;
+pub use index::{Index, IndexKind};
+pub use iter::{Entries, LogEntries, LogEntry};
+pub use options::{Options, RecoveryPolicy, SyncPolicy};
+pub use reader::ValueReader;
+pub use repair::{LostRegion, RepairReport, SegmentRepair};
+pub use segment::SegmentInfo;
+pub use shared::SharedActionKV;
+pub use snapshot::Snapshot;
+pub use transaction::Transaction;
+
+use checksum::Hasher;
+use encryption::{Decryptor, FLAG_AUTHENTICATED, FLAG_ENCRYPTED};
+use header::{Header, HEADER_LEN};
+use record::{RecordHeader, RECORD_HEADER_LEN};
+use segment::Segment;
+use transaction::ReadSet;
+
 pub type ByteStr = [u8];
 pub type ByteString = Vec<u8>;
 
@@ -14,52 +62,408 @@ pub type ByteString = Vec<u8>;
 pub struct KeyValuePair {
     pub key: ByteString,
     pub value: ByteString,
+    /// When the value stops being visible, if it was inserted with a TTL
+    pub expires_at: Option<SystemTime>,
+    /// Sequence number of the write, which increases with every write to the store; 0 for
+    /// values written before sequence numbers were recorded
+    pub seq: u64,
+    /// When the value was written, if known
+    pub timestamp: Option<SystemTime>,
+}
+
+impl KeyValuePair {
+    /// Whether the value's TTL has run out
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= SystemTime::now())
+    }
+}
+
+/// Location of a record: the segment it was written to and its offset within that segment
+///
+/// Positions order the same way as the records they point at appear in the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Position {
+    pub segment: u32,
+    pub offset: u64,
+}
+
+/// A single decoded entry from the log
+enum Record {
+    Value(KeyValuePair),
+    Tombstone(ByteString, RecordHeader),
+    /// The records written by a `WriteBatch`, with the offsets they start at, and the header
+    /// of the envelope holding them
+    Batch(Vec<(u64, Record)>, RecordHeader),
+}
+
+impl Record {
+    /// Calls `f` with the offset and contents of every value or tombstone this record holds,
+    /// given the offset the record itself starts at
+    fn for_each<F: FnMut(u64, Record)>(self, offset: u64, mut f: F) {
+        match self {
+            Record::Batch(records, _) => {
+                for (offset, record) in records {
+                    f(offset, record);
+                }
+            }
+            record => f(offset, record),
+        }
+    }
+}
+
+/// Summary of what `load` found while scanning the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoadReport {
+    /// Records read back successfully, including tombstones
+    pub records: u64,
+    /// Damaged records passed over under `RecoveryPolicy::Skip`
+    pub skipped: u64,
+    /// Bytes at the end of segments that were not part of a complete, valid record
+    pub discarded_bytes: u64,
+    /// Whether any segment was truncated back to its last good record
+    pub truncated: bool,
+    /// Whether the index was seeded from the hint file rather than a full scan
+    pub used_hint: bool,
 }
 
 #[derive(Debug)]
 pub struct ActionKV {
-    f: File,
-    pub index: HashMap<ByteString, u64>,
+    dir: PathBuf,
+    /// Every segment in the store keyed by id; the newest is the one being appended to
+    segments: BTreeMap<u32, Segment>,
+    options: Options,
+    unsynced_writes: u32,
+    last_sync: Instant,
+    loaded: bool,
+    hint_dirty: bool,
+    /// Sequence number of the latest write, as found by `load`
+    last_seq: u64,
+    /// Lock on the store directory, held until the store is dropped; snapshots go without
+    _lock: Option<lock::Lock>,
+    pub index: Index,
 }
 
 static CRC32: crc::Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
 
+/// Value length used to mark a record as a tombstone for its key
+const TOMBSTONE: u64 = u64::MAX;
+
+/// Key length used to mark a record as a batch envelope
+///
+/// The envelope's value length holds the size of its payload: the batch's records, laid out
+/// back to back exactly as they would be on their own. The envelope's checksum covers the
+/// whole payload, which is what makes the batch all-or-nothing.
+const BATCH: u32 = u32::MAX;
+
+/// Longest key the record format can hold; `Options::max_key_size` can lower the limit
+pub const MAX_KEY_SIZE: u64 = BATCH as u64 - 1;
+
+/// Longest value the record format can hold; `Options::max_value_size` can lower the limit
+pub const MAX_VALUE_SIZE: u64 = TOMBSTONE - 1;
+
 impl ActionKV {
-    pub fn open(path: &Path) -> std::io::Result<Self> {
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .append(true)
-            .open(path)?;
-        let index = HashMap::new();
-        Ok(Self { f, index })
+    /// Opens the store kept in the directory at `path`, creating it if needed
+    pub fn open(path: &Path) -> Result<Self> {
+        ActionKV::open_with(path, Options::default())
+    }
+
+    /// Opens the store at `path` with the given `options`
+    ///
+    /// Nothing can be written until `load` has read the log back, which is also where the
+    /// sequence number of the next write comes from; writes before then fail with
+    /// `ActionKvError::NotLoaded`.
+    ///
+    /// The store is locked against other handles for as long as it is open, failing with
+    /// `ActionKvError::Locked` if it is already in use; see `Options::read_only` for opening
+    /// it alongside other readers.
+    ///
+    /// Every segment's header is checked on the way in. Segments written in an older format,
+    /// including ones from before headers were added, are rewritten in the current format;
+    /// a segment from a newer format is refused with `ActionKvError::UnsupportedVersion`.
+    ///
+    /// A store kept in a single file, as written before stores became directories, is moved
+    /// into a directory at the same path as its first segment and upgraded from there. That
+    /// needs write access, so opening one read-only fails with `ActionKvError::ReadOnly`.
+    pub fn open_with(path: &Path, options: Options) -> Result<Self> {
+        if !options.read_only {
+            segment::migrate_file(path)?;
+        }
+        match std::fs::metadata(path) {
+            Ok(metadata) if !metadata.is_dir() => return Err(ActionKvError::ReadOnly),
+            Err(err) if options.read_only => return Err(err.into()),
+            Ok(_) => {}
+            Err(_) => std::fs::create_dir_all(path)?,
+        }
+        let lock = lock::acquire(path, options.read_only)?;
+
+        let found = if options.read_only {
+            segment::find(path)?.0
+        } else {
+            segment::discover(path)?
+        };
+        let newest = found.keys().next_back().copied().unwrap_or(1);
+        let mut segments = BTreeMap::new();
+        for (&id, &first) in &found {
+            if options.read_only {
+                let path = path.join(Segment::file_name(first, id));
+                if Segment::needs_upgrade(&path)? {
+                    return Err(ActionKvError::ReadOnly);
+                }
+            }
+            let writable = id == newest && !options.read_only;
+            let segment = Segment::open(path, first, id, writable, options.checksum)?;
+            segments.insert(id, segment);
+        }
+        if segments.is_empty() && options.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} holds no store", path.display()),
+            )
+            .into());
+        }
+        if segments.is_empty() {
+            let segment = Segment::open(path, newest, newest, true, options.checksum)?;
+            segments.insert(newest, segment);
+            ActionKV::sync_dir(path)?;
+        }
+
+        let index = Index::new(options.index);
+        let store = Self {
+            dir: path.to_path_buf(),
+            segments,
+            options,
+            unsynced_writes: 0,
+            last_sync: Instant::now(),
+            loaded: false,
+            hint_dirty: false,
+            last_seq: 0,
+            _lock: Some(lock),
+            index,
+        };
+
+        Ok(store)
+    }
+
+    /// Audits the store in the directory at `path` without opening it, verifying every record
+    /// and gathering statistics about the log; see `CheckReport`
+    ///
+    /// Nothing in the directory is modified, so this is safe to run against a store that is
+    /// being written to, though records appended part way through may or may not be seen.
+    /// A segment written by a newer build fails the check with
+    /// `ActionKvError::UnsupportedVersion` rather than being reported as damaged.
+    pub fn check(path: &Path) -> Result<CheckReport> {
+        check::check(path)
+    }
+
+    /// Salvages every intact record from the damaged segments of the store at `path`; see
+    /// `RepairReport`
+    ///
+    /// Each damaged segment is rewritten without the regions that couldn't be read, keeping
+    /// the original alongside it as `<name>.corrupt`. The store is locked while it is being
+    /// repaired, failing with `ActionKvError::Locked` if it is open elsewhere.
+    pub fn repair(path: &Path) -> Result<RepairReport> {
+        repair::repair(path)
+    }
+
+    /// Rebuilds `index` by scanning every record in the store, oldest segment first
+    ///
+    /// A record cut short by a crash during a write is only ever found at the end of the
+    /// newest segment, with an intact header claiming more data than the file holds, or
+    /// with the file ending part way through its header. It is left out of the index and
+    /// reported in `LoadReport::discarded_bytes`. Anything else, be it a record that fails
+    /// its checksum or one cut short in a segment that has already been sealed, is damage,
+    /// and is dealt with as `Options::recovery` says.
+    ///
+    /// With `Options::hint_file` set, a valid hint file seeds the index and only the records
+    /// appended after it was written are scanned.
+    pub fn load(&mut self) -> Result<LoadReport> {
+        let mut report = LoadReport::default();
+
+        let mut resume_at = None;
+        if self.options.hint_file {
+            if let Some((index, end, last_seq)) = hint::read(&self.dir, &self.segments)? {
+                self.index = index.into_kind(self.options.index);
+                self.last_seq = last_seq;
+                resume_at = Some(end);
+                report.used_hint = true;
+            }
+        }
+
+        let newest = self.active().id;
+        for segment in self.segments.values_mut() {
+            let start = match resume_at {
+                Some(end) if segment.id < end.segment => continue,
+                Some(end) if segment.id == end.segment => end.offset,
+                _ => HEADER_LEN,
+            };
+            ActionKV::load_segment(
+                segment,
+                segment.id == newest,
+                start,
+                &mut self.index,
+                &mut self.last_seq,
+                &self.options,
+                &mut report,
+            )?;
+        }
+
+        self.loaded = true;
+        Ok(report)
     }
 
-    pub fn load(&mut self) -> std::io::Result<()> {
-        let mut f = BufReader::new(&mut self.f);
+    /// Applies the records in `segment` from `start` onwards to `index`, raising `last_seq`
+    /// to the highest sequence number found
+    ///
+    /// Only the `active` segment, the one being appended to, can end in a torn write.
+    fn load_segment(
+        segment: &mut Segment,
+        active: bool,
+        start: u64,
+        index: &mut Index,
+        last_seq: &mut u64,
+        options: &Options,
+        report: &mut LoadReport,
+    ) -> Result<()> {
+        let file_len = segment.len;
+        let mut truncate_at = None;
+
+        let mut f = BufReader::new(segment.reader());
+        f.seek(SeekFrom::Start(start))?;
 
         loop {
-            let pos = f.seek(SeekFrom::Current(0))?;
-
-            let maybe_kv = ActionKV::process_record(&mut f);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => match err.kind() {
-                    std::io::ErrorKind::UnexpectedEof => {
-                        break;
+            let pos = f.stream_position()?;
+
+            let maybe_record = ActionKV::read_record(
+                &mut f,
+                pos,
+                file_len,
+                segment.header.checksum,
+                Decryptor::default(),
+                false,
+            );
+            let record = match maybe_record {
+                Ok(record) => record,
+                Err(err) if err.is_eof() => break,
+                Err(ActionKvError::Truncated { .. }) if active => {
+                    report.discarded_bytes += file_len - pos;
+                    if options.truncate_torn_tail {
+                        truncate_at = Some(pos);
+                    }
+                    break;
+                }
+                Err(err @ (ActionKvError::Corruption { .. } | ActionKvError::Truncated { .. })) => {
+                    match options.recovery {
+                        RecoveryPolicy::Fail => return Err(err),
+                        RecoveryPolicy::Skip => {
+                            report.skipped += 1;
+                            match ActionKV::skip_damaged(&mut f, file_len, pos)? {
+                                Some(next) => {
+                                    f.seek(SeekFrom::Start(next))?;
+                                    continue;
+                                }
+                                None => {
+                                    report.discarded_bytes += file_len - pos;
+                                    break;
+                                }
+                            }
+                        }
+                        RecoveryPolicy::Truncate => {
+                            report.discarded_bytes += file_len - pos;
+                            truncate_at = Some(pos);
+                            break;
+                        }
                     }
-                    _ => return Err(err),
-                },
+                }
+                Err(err) => return Err(err),
             };
 
-            self.index.insert(kv.key, pos);
+            // Batch envelopes normally have no sequence number of their own, but the empty one
+            // left by a merge carries the latest at the time
+            if let Record::Batch(_, header) = &record {
+                *last_seq = (*last_seq).max(header.seq);
+            }
+            record.for_each(pos, |offset, record| {
+                report.records += 1;
+
+                let position = Position {
+                    segment: segment.id,
+                    offset,
+                };
+                match record {
+                    // An expired value still hides any older value for its key
+                    Record::Value(kv) if kv.is_expired() => {
+                        *last_seq = (*last_seq).max(kv.seq);
+                        index.remove(&kv.key);
+                    }
+                    Record::Value(kv) => {
+                        *last_seq = (*last_seq).max(kv.seq);
+                        index.insert(kv.key, position);
+                    }
+                    Record::Tombstone(key, header) => {
+                        *last_seq = (*last_seq).max(header.seq);
+                        index.remove(&key);
+                    }
+                    Record::Batch(..) => unreachable!("batches are unpacked by for_each"),
+                }
+            });
+        }
+
+        if let Some(pos) = truncate_at.filter(|_| !options.read_only) {
+            OpenOptions::new()
+                .write(true)
+                .open(&segment.path)?
+                .set_len(pos)?;
+            segment.len = pos;
+            report.truncated = true;
         }
 
         Ok(())
     }
 
-    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> std::io::Result<()> {
+    /// Finds where to carry on scanning a segment `len` bytes long past the damaged record at
+    /// `pos`, which `f` stopped reading at, or `None` if nothing intact follows
+    ///
+    /// Reading only gets past the header if its checksum matched, in which case the record's
+    /// length can be trusted and the scan resumes after it. Otherwise the rest of the segment
+    /// is searched a byte at a time for the next header that checks out. `f` is left
+    /// wherever the search took it.
+    pub(crate) fn skip_damaged<R: Read + Seek>(
+        f: &mut R,
+        len: u64,
+        pos: u64,
+    ) -> Result<Option<u64>> {
+        let end = f.stream_position()?;
+        if end > pos + RECORD_HEADER_LEN {
+            return Ok((end < len).then_some(end));
+        }
+
+        let header_len = RECORD_HEADER_LEN as usize;
+        f.seek(SeekFrom::Start(pos + 1))?;
+        let mut base = pos + 1;
+        let mut window = ByteString::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = f.read(&mut buf)?;
+            window.extend_from_slice(&buf[..n]);
+
+            let mut start = 0;
+            while start + header_len <= window.len() {
+                let header = &window[start..start + header_len];
+                if RecordHeader::decode(header, base + start as u64).is_ok() {
+                    return Ok(Some(base + start as u64));
+                }
+                start += 1;
+            }
+            if n == 0 {
+                return Ok(None);
+            }
+            window.drain(..start);
+            base += start as u64;
+        }
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
         let pos = self.insert_but_ignore_index(key, value)?;
 
         self.index.insert(key.to_vec(), pos);
@@ -68,117 +472,1131 @@ impl ActionKV {
 
     /// Inserts data into the log structured store without updating the KV internal index
     ///
-    /// Inserted data is added in the format <checksum><key_len><value_len><value>; This is to
-    /// ensure resiliency of the stored data.
-    pub fn insert_but_ignore_index(
+    /// Inserted data is added in the format
+    /// <checksum><flags><key_len><value_len><expires_at><seq><timestamp><header_checksum>
+    /// <key><value>; This is to ensure resiliency of the stored data. The value is compressed
+    /// according to `Options::compression`, then encrypted if `Options::encryption_key` is set.
+    pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> Result<Position> {
+        self.append_record(key, Some(value), self.options.compression, 0)
+    }
+
+    /// Inserts a value that `get` treats as missing once `ttl` has passed
+    ///
+    /// The expiry time is stored in the record. Expired values are skipped by `load` and
+    /// dropped from disk by compaction.
+    pub fn insert_with_ttl(&mut self, key: &ByteStr, value: &ByteStr, ttl: Duration) -> Result<()> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let expires_at = record::now_millis().saturating_add(ttl);
+        let pos = self.append_record(key, Some(value), self.options.compression, expires_at)?;
+
+        self.index.insert(key.to_vec(), pos);
+        Ok(())
+    }
+
+    /// Inserts a value compressed with `compression` rather than the store's default
+    pub fn insert_compressed(
         &mut self,
         key: &ByteStr,
         value: &ByteStr,
+        compression: Compression,
+    ) -> Result<()> {
+        let pos = self.append_record(key, Some(value), compression, 0)?;
+
+        self.index.insert(key.to_vec(), pos);
+        Ok(())
+    }
+
+    /// Appends a record to the newest segment, returning the position it was written at
+    ///
+    /// A `None` value writes a tombstone: the key followed by no value bytes, with the
+    /// value length set to `TOMBSTONE`. A value counts as missing from `expires_at`, in
+    /// milliseconds since the Unix epoch, unless that is 0.
+    ///
+    /// The record is encoded up front and handed to the file in a single write, after which
+    /// the log is synced if the store's `SyncPolicy` calls for it. A new segment is started
+    /// first if the record would take the current one past `Options::max_segment_size`.
+    fn append_record(
+        &mut self,
+        key: &ByteStr,
+        value: Option<&ByteStr>,
+        compression: Compression,
+        expires_at: u64,
+    ) -> Result<Position> {
+        self.check_writable()?;
+        self.check_size(key, value.map(|value| value.len() as u64))?;
+
+        let header = RecordHeader {
+            expires_at,
+            ..self.next_header()
+        };
+        let mut buf = ByteString::new();
+        ActionKV::write_record(&mut buf, &self.options, compression, header, key, value)?;
+
+        self.append(&buf)
+    }
+
+    /// Refuses to work from `index` before `load` has built it, which would take every
+    /// record missing from it for dead
+    fn check_loaded(&self) -> Result<()> {
+        if !self.loaded {
+            return Err(ActionKvError::NotLoaded);
+        }
+
+        Ok(())
+    }
+
+    /// Refuses writes to a store opened with `Options::read_only`, and to one that hasn't been
+    /// loaded yet, as the sequence number to write with isn't known until then
+    fn check_writable(&self) -> Result<()> {
+        if self.options.read_only {
+            return Err(ActionKvError::ReadOnly);
+        }
+
+        self.check_loaded()
+    }
+
+    /// Checks the length of a key and value about to be written against the store's limits
+    fn check_size(&self, key: &ByteStr, value_len: Option<u64>) -> Result<()> {
+        let limit = self.options.max_key_size.min(MAX_KEY_SIZE);
+        let len = key.len() as u64;
+        if len > limit {
+            return Err(ActionKvError::KeyTooLarge { len, limit });
+        }
+
+        let limit = self.options.max_value_size.min(MAX_VALUE_SIZE);
+        match value_len {
+            Some(len) if len > limit => Err(ActionKvError::ValueTooLarge { len, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes the next sequence number, returning a header for a record written now that
+    /// carries it
+    fn next_header(&mut self) -> RecordHeader {
+        self.last_seq += 1;
+        RecordHeader {
+            seq: self.last_seq,
+            timestamp: record::now_millis(),
+            ..RecordHeader::default()
+        }
+    }
+
+    /// Writes already encoded records to the end of the newest segment
+    fn append(&mut self, buf: &ByteStr) -> Result<Position> {
+        self.make_room(buf.len() as u64)?;
+
+        let active = self.active_mut();
+        let next_byte = SeekFrom::End(0);
+        let current_position = active.f.seek(next_byte)?;
+        active.f.write_all(buf)?;
+        active.len = current_position + buf.len() as u64;
+        let position = Position {
+            segment: active.id,
+            offset: current_position,
+        };
+
+        self.written()?;
+        Ok(position)
+    }
+
+    /// Starts a new segment if writing `len` more bytes would take the current one past
+    /// `Options::max_segment_size`
+    ///
+    /// A record larger than that on its own still goes into a segment, just one by itself.
+    /// Records are always written with the configured checksum, so a segment started with a
+    /// different one is left behind too. That waits for the first write rather than happening
+    /// on open, so that `load` still treats a torn write at the end of it as the tail of the log.
+    fn make_room(&mut self, len: u64) -> Result<()> {
+        let active = self.active();
+        if active.header.checksum != self.options.checksum
+            || (!active.is_empty() && active.len + len > self.options.max_segment_size)
+        {
+            self.rotate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Accounts for a write that has reached the log, syncing if the `SyncPolicy` calls for it
+    fn written(&mut self) -> Result<()> {
+        self.unsynced_writes += 1;
+        self.hint_dirty = true;
+        let due = match self.options.sync {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::EveryNWrites(n) => self.unsynced_writes >= n,
+            SyncPolicy::OnWriteAfter(interval) => self.last_sync.elapsed() >= interval,
+        };
+        if due {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Inserts a value of `len` bytes read from `value`, without holding it all in memory
+    ///
+    /// Exactly `len` bytes are read. The record's checksum isn't known until the whole value
+    /// has been read, so it is filled in last; a crash before then leaves a record at the end
+    /// of the log with a checksum of 0, which `load` discards like any other torn write.
+    /// If `value` fails or runs out early, the partial record is removed again and nothing is
+    /// inserted.
+    ///
+    /// Values are stored uncompressed. With `Options::encryption_key` set the value has to be
+    /// encrypted as a whole, so it is read into memory first.
+    pub fn insert_from_reader<R: Read>(&mut self, key: &ByteStr, value: R, len: u64) -> Result<()> {
+        self.check_writable()?;
+        self.check_size(key, Some(len))?;
+        if self.options.encryption_key.is_some() {
+            let mut buf = ByteString::new();
+            value.take(len).read_to_end(&mut buf)?;
+            if buf.len() as u64 != len {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("value ended after {} of {} bytes", buf.len(), len),
+                )
+                .into());
+            }
+
+            let pos = self.append_record(key, Some(&buf), Compression::None, 0)?;
+            self.index.insert(key.to_vec(), pos);
+            return Ok(());
+        }
+
+        self.make_room(RECORD_HEADER_LEN + key.len() as u64 + len)?;
+
+        let header = RecordHeader {
+            key_len: key.len() as u32,
+            value_len: len,
+            ..self.next_header()
+        };
+        let active = self.active_mut();
+        let start = active.f.seek(SeekFrom::End(0))?;
+        if let Err(err) = ActionKV::stream_record(active, start, header, key, value) {
+            active.f.set_len(start)?;
+            active.len = start;
+            return Err(err);
+        }
+        active.len = start + RECORD_HEADER_LEN + key.len() as u64 + len;
+        let position = Position {
+            segment: active.id,
+            offset: start,
+        };
+
+        self.written()?;
+        self.index.insert(key.to_vec(), position);
+        Ok(())
+    }
+
+    /// Writes a record with `header` to `segment` at `start`, its end, copying the value
+    /// from `value`
+    fn stream_record<R: Read>(
+        segment: &Segment,
+        start: u64,
+        header: RecordHeader,
+        key: &ByteStr,
+        mut value: R,
+    ) -> Result<()> {
+        let mut f = &segment.f;
+        let len = header.value_len;
+        let mut head = ByteString::with_capacity(RECORD_HEADER_LEN as usize + key.len());
+        header.write(&mut head, 0)?;
+        head.extend_from_slice(key);
+        f.write_all(&head)?;
+
+        let mut hasher = header.hasher(segment.header.checksum);
+        hasher.update(key);
+        let mut buf = vec![0; 64 * 1024];
+        let mut remaining = len;
+        while remaining > 0 {
+            let wanted = remaining.min(buf.len() as u64) as usize;
+            let n = match value.read(&mut buf[..wanted]) {
+                Ok(0) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!("value ended after {} of {} bytes", len - remaining, len),
+                    )
+                    .into())
+                }
+                Ok(n) => n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            hasher.update(&buf[..n]);
+            f.write_all(&buf[..n])?;
+            remaining -= n as u64;
+        }
+
+        // The segment is open for appending, which rules out writing anywhere but the end
+        let mut f = OpenOptions::new().write(true).open(&segment.path)?;
+        f.seek(SeekFrom::Start(start))?;
+        f.write_u64::<LittleEndian>(hasher.finish())?;
+
+        Ok(())
+    }
+
+    /// Applies every insert and delete in `batch` atomically
+    ///
+    /// The batch is written as one record, so it either survives a crash as a whole or is
+    /// discarded as a whole by `load`.
+    pub fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        self.check_writable()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut payload = ByteString::new();
+        let mut offsets = Vec::with_capacity(batch.len());
+        for (key, value) in &batch.ops {
+            self.check_size(key, value.as_ref().map(|value| value.len() as u64))?;
+            offsets.push(payload.len() as u64);
+            let header = self.next_header();
+            ActionKV::write_record(
+                &mut payload,
+                &self.options,
+                self.options.compression,
+                header,
+                key,
+                value.as_deref(),
+            )?;
+        }
+
+        let header = RecordHeader {
+            timestamp: record::now_millis(),
+            ..RecordHeader::new(BATCH, payload.len() as u64)
+        };
+        let mut hasher = header.hasher(self.options.checksum);
+        hasher.update(&payload);
+        let mut buf = ByteString::with_capacity(RECORD_HEADER_LEN as usize + payload.len());
+        header.write(&mut buf, hasher.finish())?;
+        buf.extend_from_slice(&payload);
+
+        let pos = self.append(&buf)?;
+        for ((key, value), offset) in batch.ops.into_iter().zip(offsets) {
+            match value {
+                Some(_) => {
+                    let position = Position {
+                        segment: pos.segment,
+                        offset: pos.offset + RECORD_HEADER_LEN + offset,
+                    };
+                    self.index.insert(key, position);
+                }
+                None => {
+                    self.index.remove(&key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The segment currently being appended to
+    fn active(&self) -> &Segment {
+        self.segments.values().next_back().unwrap()
+    }
+
+    fn active_mut(&mut self) -> &mut Segment {
+        self.segments.values_mut().next_back().unwrap()
+    }
+
+    /// Seals the current segment and starts appending to a new one
+    fn rotate(&mut self) -> Result<()> {
+        let active = self.active_mut();
+        active.seal()?;
+
+        let id = active.id + 1;
+        self.segments.insert(
+            id,
+            Segment::open(&self.dir, id, id, true, self.options.checksum)?,
+        );
+        ActionKV::sync_dir(&self.dir)?;
+
+        self.unsynced_writes = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Pushes any writes still buffered by the store to the operating system
+    ///
+    /// Records are handed to the file as they are written, so this only matters for the
+    /// file's own buffering; use `sync` to make writes durable.
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.active_mut().f.flush()?)
+    }
+
+    /// Forces every write made so far to stable storage, regardless of the `SyncPolicy`
+    pub fn sync(&mut self) -> Result<()> {
+        self.active_mut().f.sync_data()?;
+        self.unsynced_writes = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Serializes a single record to `f`, returning the number of bytes written
+    ///
+    /// The record is checksummed and encrypted as `options` say, and its value compressed
+    /// with `compression`. Its expiry time, sequence number and timestamp are taken from
+    /// `header`, and the rest of the header is filled in to match the key and value.
+    fn write_record<W: Write>(
+        f: &mut W,
+        options: &Options,
+        compression: Compression,
+        header: RecordHeader,
+        key: &ByteStr,
+        value: Option<&ByteStr>,
     ) -> std::io::Result<u64> {
-        let mut f = BufWriter::new(&mut self.f);
+        let (flags, value, value_len) = match value {
+            Some(value) => {
+                let (mut flags, mut value) = compression.compress(value)?;
+                if let Some(encryption_key) = &options.encryption_key {
+                    flags |= FLAG_ENCRYPTED | FLAG_AUTHENTICATED;
+                    let header = RecordHeader { flags, ..header };
+                    value = Cow::Owned(encryption::encrypt(encryption_key, &header, key, &value)?);
+                }
+                let value_len = value.len() as u64;
+                (flags, value, value_len)
+            }
+            None => (0, Cow::Borrowed(&[][..]), TOMBSTONE),
+        };
 
         let key_len = key.len();
-        let value_len = value.len();
-        let mut tmp = ByteString::with_capacity(key_len + value_len);
+        let mut tmp = ByteString::with_capacity(key_len + value.len());
 
         for byte in key {
             tmp.push(*byte);
         }
-        for byte in value {
+        for byte in value.iter() {
             tmp.push(*byte);
         }
 
-        let checksum = CRC32.checksum(&tmp);
+        let header = RecordHeader {
+            flags,
+            key_len: key_len as u32,
+            value_len,
+            ..header
+        };
+        let mut hasher = header.hasher(options.checksum);
+        hasher.update(&tmp);
 
-        let next_byte = SeekFrom::End(0);
-        let current_position = f.seek(SeekFrom::Current(0))?;
-        f.seek(next_byte)?;
-        f.write_u32::<LittleEndian>(checksum)?;
-        f.write_u32::<LittleEndian>(key_len as u32)?;
-        f.write_u32::<LittleEndian>(value_len as u32)?;
+        header.write(f, hasher.finish())?;
         f.write_all(&tmp)?;
 
-        Ok(current_position)
+        Ok(RECORD_HEADER_LEN + tmp.len() as u64)
+    }
+
+    /// Rewrites the store so that it only contains the live entries held in `index`
+    ///
+    /// The current segment is sealed and every segment is then merged into one; see `merge`.
+    /// Fails with `ActionKvError::NotLoaded` if the store hasn't been loaded.
+    pub fn compact(&mut self) -> Result<()> {
+        self.check_writable()?;
+        if !self.active().is_empty() {
+            self.rotate()?;
+        }
+
+        let oldest = *self.segments.keys().next().unwrap();
+        let newest_sealed = self.active().id - 1;
+        if oldest <= newest_sealed {
+            self.merge(oldest..=newest_sealed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges the sealed segments with ids in `ids` into a single segment holding only their
+    /// live entries
+    ///
+    /// Values that have since been overwritten, deleted or expired are dropped. While an older
+    /// segment outside the merge could still hold a value for a key, a tombstone is left in
+    /// place of the versions dropped, so that neither `load` nor `get_as_of` finds that value.
+    /// The merged segment ends with an empty batch carrying the store's latest sequence
+    /// number, which would otherwise be lost along with the records holding it.
+    /// The merged segment is synced to disk and renamed into place before the segments it
+    /// replaces are removed, and `open` finishes off a merge interrupted in between, so a
+    /// crash part way through never loses data. Once swapped in, `index` is updated with the
+    /// new offsets. Fails with `ActionKvError::NotLoaded` if the store hasn't been loaded.
+    pub fn merge(&mut self, ids: RangeInclusive<u32>) -> Result<()> {
+        self.check_writable()?;
+        if ids.contains(&self.active().id) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the segment being written to can't be merged",
+            )
+            .into());
+        }
+
+        let merged: Vec<u32> = self.segments.range(ids).map(|(id, _)| *id).collect();
+        let (oldest, newest) = match (merged.first(), merged.last()) {
+            (Some(oldest), Some(newest)) => (*oldest, *newest),
+            _ => return Ok(()),
+        };
+        let first = self.segments[&oldest].first;
+        let has_older = *self.segments.keys().next().unwrap() < oldest;
+
+        let mut live: Vec<Position> = self
+            .index
+            .iter()
+            .map(|(_, pos)| pos)
+            .filter(|pos| merged.contains(&pos.segment))
+            .copied()
+            .collect();
+        live.sort_unstable();
+
+        // Every key with a version left behind gets a tombstone in its place while an older
+        // segment outside the merge could still hold a value for it. The tombstone takes the
+        // sequence number and timestamp of the oldest version dropped, which comes first in
+        // the log, so that neither `load` nor `get_as_of` falls back on that older value.
+        // Only the keys and sequence numbers are wanted from the scan, so values are checked
+        // but not read back or decoded.
+        let mut tombstones = BTreeMap::new();
+        if has_older {
+            let segments = self.segments.range(oldest..=newest);
+            let recovery = self.options.recovery;
+            let active = self.active().id;
+            for entry in LogEntries::new(segments, recovery, Decryptor::default(), false, active) {
+                let entry = entry?;
+                if self.index.get(&entry.key) == Some(&entry.position) {
+                    continue;
+                }
+                let header = RecordHeader {
+                    seq: entry.seq,
+                    timestamp: entry.timestamp.map_or(0, record::to_millis),
+                    ..RecordHeader::default()
+                };
+                tombstones.entry(entry.key).or_insert(header);
+            }
+        }
+
+        let file_name = Segment::file_name(first, newest);
+        let path = self.dir.join(&file_name);
+        let tmp_path = self.dir.join(file_name + ".tmp");
+
+        let mut new_positions = Vec::with_capacity(live.len());
+        let mut expired = Vec::new();
+        let mut expired_tombstones = Vec::new();
+        {
+            let tmp_file = File::create(&tmp_path)?;
+            let mut f = BufWriter::new(&tmp_file);
+            let checksum = self.options.checksum;
+            f.write_all(&Header::new(checksum).encode())?;
+            let mut offset = HEADER_LEN;
+
+            // Tombstones go first, so that the values that follow them win when loading
+            let options = &self.options;
+            for (key, header) in &tombstones {
+                offset +=
+                    ActionKV::write_record(&mut f, options, Compression::None, *header, key, None)?;
+            }
+
+            // Values are streamed across, which verifies them once they have been read to the
+            // end. Their checksums are carried over unless they were written with a different
+            // algorithm, in which case new ones are worked out along the way. Expired values
+            // are left behind, with a tombstone in their place if an older value could be
+            // lurking outside the merge.
+            let now = record::now_millis();
+            for pos in live {
+                let mut value = self.value_reader_at(pos)?;
+                let key = std::mem::take(&mut value.key);
+                let header = value.header;
+                if header.is_expired(now) {
+                    if has_older && !tombstones.contains_key(&key) {
+                        let tombstone = RecordHeader {
+                            seq: header.seq,
+                            timestamp: header.timestamp,
+                            ..RecordHeader::default()
+                        };
+                        expired_tombstones.push((key.clone(), tombstone));
+                    }
+                    expired.push(key);
+                    continue;
+                }
+
+                if self.segments[&pos.segment].header.checksum == checksum {
+                    header.write(&mut f, value.checksum)?;
+                    f.write_all(&key)?;
+                    std::io::copy(&mut value, &mut f)?;
+                } else {
+                    let mut data = Read::chain(&key[..], &mut value);
+                    upgrade::copy_record(&mut data, &mut f, header, None, checksum)?;
+                }
+                new_positions.push((key, offset));
+                offset += RECORD_HEADER_LEN + header.data_len();
+            }
+            for (key, header) in expired_tombstones {
+                ActionKV::write_record(&mut f, options, Compression::None, header, &key, None)?;
+            }
+
+            // The records holding the latest sequence numbers may just have been dropped, so
+            // an empty batch carries the latest one on, keeping `load` from handing the same
+            // numbers out again
+            let marker = RecordHeader {
+                seq: self.last_seq,
+                timestamp: record::now_millis(),
+                ..RecordHeader::new(BATCH, 0)
+            };
+            marker.write(&mut f, marker.hasher(checksum).finish())?;
+
+            f.flush()?;
+            drop(f);
+            tmp_file.sync_all()?;
+        }
+
+        std::fs::rename(&tmp_path, &path)?;
+        ActionKV::sync_dir(&self.dir)?;
+
+        for id in &merged {
+            let segment = self.segments.remove(id).unwrap();
+            if segment.path != path {
+                std::fs::remove_file(&segment.path)?;
+            }
+        }
+        ActionKV::sync_dir(&self.dir)?;
+
+        self.segments.insert(
+            newest,
+            Segment::open(&self.dir, first, newest, false, self.options.checksum)?,
+        );
+        for (key, offset) in new_positions {
+            let position = Position {
+                segment: newest,
+                offset,
+            };
+            self.index.insert(key, position);
+        }
+        for key in expired {
+            self.index.remove(&key);
+        }
+
+        if self.options.hint_file {
+            self.write_hint()?;
+        }
+
+        Ok(())
     }
 
-    pub fn get(&mut self, key: &ByteStr) -> std::io::Result<Option<ByteString>> {
+    /// Saves `index` to the hint file in the store directory so that `load` can skip the
+    /// records it covers
+    ///
+    /// The hint reflects the index as it currently stands, so it can only be written once
+    /// the store has been loaded, failing with `ActionKvError::NotLoaded` before then. With
+    /// `Options::hint_file` set this also happens when the store is compacted or dropped
+    /// after being written to.
+    pub fn write_hint(&mut self) -> Result<()> {
+        self.check_writable()?;
+        hint::write(&self.dir, &self.segments, &self.index, self.last_seq)?;
+        self.hint_dirty = false;
+
+        Ok(())
+    }
+
+    /// Persists file creations, renames and removals within `dir`; a no-op where directories
+    /// can't be synced
+    fn sync_dir(dir: &Path) -> std::io::Result<()> {
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = dir;
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>> {
+        Ok(self.get_with_meta(key)?.map(|kv| kv.value))
+    }
+
+    /// Looks up `key` like `get`, also returning when the value was written, its sequence
+    /// number and when it expires
+    pub fn get_with_meta(&self, key: &ByteStr) -> Result<Option<KeyValuePair>> {
         let pos = match self.index.get(key) {
             None => return Ok(None),
             Some(pos) => *pos,
         };
 
         let kv = self.get_at(pos)?;
+        if kv.is_expired() {
+            return Ok(None);
+        }
 
-        Ok(Some(kv.value))
+        Ok(Some(kv))
     }
 
-    pub fn get_at(&mut self, position: u64) -> std::io::Result<KeyValuePair> {
-        let mut f = BufReader::new(&mut self.f);
-        f.seek(SeekFrom::Start(position))?;
-        let kv = ActionKV::process_record(&mut f)?;
+    /// Reads the record at `position`
+    ///
+    /// Reads go through positional reads rather than seeking the segment file, so they only
+    /// need a shared reference to the store. Expired values are returned all the same.
+    pub fn get_at(&self, position: Position) -> Result<KeyValuePair> {
+        let segment = self.segment(position.segment)?;
+        let mut f = segment.reader();
+        f.seek(SeekFrom::Start(position.offset))?;
 
-        Ok(kv)
+        let record = ActionKV::process_record(
+            &mut f,
+            position.offset,
+            segment.header.checksum,
+            Decryptor::new(&self.options),
+            true,
+        )?;
+        match record {
+            Record::Value(kv) => Ok(kv),
+            Record::Tombstone(..) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("record at position {:?} is a tombstone", position),
+            )
+            .into()),
+            Record::Batch(..) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("record at position {:?} is a batch", position),
+            )
+            .into()),
+        }
     }
 
-    pub fn find(&mut self, target: &ByteStr) -> std::io::Result<Option<(u64, ByteString)>> {
-        let mut f = BufReader::new(&mut self.f);
+    /// Describes the segment files making up the store, oldest first
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        self.segments.values().map(Segment::info).collect()
+    }
 
-        let mut found: Option<(u64, ByteString)> = None;
-        loop {
-            let pos = f.seek(SeekFrom::Current(0))?;
-
-            let maybe_kv = ActionKV::process_record(&mut f);
-            let kv = match maybe_kv {
-                Ok(kv) => kv,
-                Err(err) => match err.kind() {
-                    std::io::ErrorKind::UnexpectedEof => {
-                        break;
-                    }
-                    _ => return Err(err),
-                },
-            };
+    /// Takes a consistent, read-only view of the store that later writes don't affect
+    ///
+    /// The index is copied, which makes this O(n) in the number of keys, but no data is
+    /// read or copied. See `Snapshot`.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let mut segments = BTreeMap::new();
+        for (id, segment) in &self.segments {
+            segments.insert(*id, segment.try_clone()?);
+        }
+
+        Ok(Snapshot::new(ActionKV {
+            dir: self.dir.clone(),
+            segments,
+            options: self.options.clone(),
+            unsynced_writes: 0,
+            last_sync: Instant::now(),
+            loaded: self.loaded,
+            hint_dirty: false,
+            last_seq: self.last_seq,
+            _lock: None,
+            index: self.index.clone(),
+        }))
+    }
+
+    /// Returns a reader over the value stored for `key`, for values too large to hold in memory
+    pub fn get_reader(&self, key: &ByteStr) -> Result<Option<ValueReader<'_>>> {
+        let pos = match self.index.get(key) {
+            None => return Ok(None),
+            Some(pos) => *pos,
+        };
+
+        let value = self.value_reader_at(pos)?;
+        if value.header.is_expired(record::now_millis()) {
+            return Ok(None);
+        }
+
+        Ok(Some(value.decoded(Decryptor::new(&self.options))?))
+    }
+
+    /// Reads the header and key of the value record at `position`, leaving the value to be
+    /// streamed as it is stored
+    fn value_reader_at(&self, position: Position) -> Result<ValueReader<'_>> {
+        let segment = self.segment(position.segment)?;
+        let mut f = segment.reader();
+        f.seek(SeekFrom::Start(position.offset))?;
+
+        let mut header = ByteString::with_capacity(RECORD_HEADER_LEN as usize);
+        f.by_ref()
+            .take(RECORD_HEADER_LEN)
+            .read_to_end(&mut header)?;
+        if header.len() as u64 != RECORD_HEADER_LEN {
+            return Err(ActionKvError::Truncated {
+                offset: position.offset,
+            });
+        }
 
-            if kv.key == target {
-                found = Some((pos, kv.value));
+        let (saved_checksum, header) = RecordHeader::decode(&header, position.offset)?;
+        if header.is_batch() || header.is_tombstone() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("record at position {:?} doesn't hold a value", position),
+            )
+            .into());
+        }
+
+        let mut hasher = header.hasher(segment.header.checksum);
+        let mut key = ByteString::new();
+        if !ActionKV::read_data(&mut f, header.key_len as u64, &mut hasher, Some(&mut key))? {
+            return Err(ActionKvError::Truncated {
+                offset: position.offset,
+            });
+        }
+
+        Ok(ValueReader::new(
+            f,
+            position.offset,
+            key,
+            saved_checksum,
+            header,
+            hasher,
+        ))
+    }
+
+    fn segment(&self, id: u32) -> Result<&Segment> {
+        match self.segments.get(&id) {
+            Some(segment) => Ok(segment),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no segment with id {}", id),
+            )
+            .into()),
+        }
+    }
+
+    /// Returns every live entry in the store
+    ///
+    /// Entries come in key order with an ordered index, otherwise in the order they appear in
+    /// the log. Values are read from the log as the iterator reaches them.
+    pub fn iter(&self) -> Entries<'_> {
+        let mut positions: Vec<Position> = self.index.iter().map(|(_, pos)| *pos).collect();
+        if self.index.kind() == IndexKind::Hashed {
+            positions.sort_unstable();
+        }
+
+        Entries::new(self, positions)
+    }
+
+    /// Returns every record in the log in the order it was written, including values that
+    /// have since been overwritten and tombstones for deleted keys
+    pub fn iter_log(&self) -> LogEntries<'_> {
+        LogEntries::new(
+            self.segments.range(..),
+            self.options.recovery,
+            Decryptor::new(&self.options),
+            true,
+            self.active().id,
+        )
+    }
+
+    /// Returns the entries whose keys fall within `range`, in key order
+    ///
+    /// Values are read from the log as the iterator reaches them. Bounds are byte slices, e.g.
+    /// `store.range(&b"a"[..]..&b"n"[..])`.
+    pub fn range<'k, R: RangeBounds<&'k ByteStr>>(&self, range: R) -> Entries<'_> {
+        Entries::new(self, self.index.range_positions(range))
+    }
+
+    /// Returns the entries whose keys start with `prefix`, in key order
+    pub fn scan_prefix(&self, prefix: &ByteStr) -> Entries<'_> {
+        Entries::new(self, self.index.prefix_positions(prefix))
+    }
+
+    pub fn find(&self, target: &ByteStr) -> Result<Option<(Position, ByteString)>> {
+        let mut found: Option<(Position, ByteString)> = None;
+
+        for entry in self.iter_log() {
+            let entry = entry?;
+            if entry.key == target {
+                found = entry.value.map(|value| (entry.position, value));
             }
         }
 
         Ok(found)
     }
 
+    /// Returns every version of `key` still in the log, oldest first, including tombstones
+    /// for the times it was deleted
+    ///
+    /// Versions are ordered by sequence number, falling back on their order in the log for
+    /// those written before sequence numbers were recorded. Compaction discards versions that
+    /// have been overwritten, so only those written since the last compaction are sure to be
+    /// there. Where older versions survive outside a merge, the versions it discarded are
+    /// replaced by a single tombstone carrying the sequence number of the oldest of them.
+    pub fn history(&self, key: &ByteStr) -> Result<Vec<LogEntry>> {
+        let mut versions = Vec::new();
+        for entry in self.iter_log() {
+            let entry = entry?;
+            if entry.key == key {
+                versions.push(entry);
+            }
+        }
+        versions.sort_by_key(|entry| entry.seq);
+
+        Ok(versions)
+    }
+
+    /// Reads the value `key` had once the write with sequence number `seq` had been made
+    ///
+    /// Returns `None` if the key had been deleted or not yet written at that point, or if the
+    /// version in question has since been discarded by compaction; an older version is never
+    /// returned in its place. TTLs aren't taken into account.
+    pub fn get_as_of(&self, key: &ByteStr, seq: u64) -> Result<Option<ByteString>> {
+        let version = self
+            .history(key)?
+            .into_iter()
+            .rev()
+            .find(|entry| entry.seq <= seq);
+
+        Ok(version.and_then(|entry| entry.value))
+    }
+
+    /// Sequence number of the latest write to the store
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
     #[inline]
-    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> std::io::Result<()> {
+    pub fn update(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
         self.insert(key, value)
     }
 
-    #[inline]
-    pub fn delete(&mut self, key: &ByteStr) -> std::io::Result<()> {
-        self.insert(key, b"")
+    /// Sets `key` to `new` only if its current value is `expected`, where `None` means the
+    /// key is missing
+    ///
+    /// Fails with `ActionKvError::Conflict` and writes nothing if the value is anything else.
+    /// Expired values count as missing, as they do for `get`.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &ByteStr,
+        expected: Option<&ByteStr>,
+        new: &ByteStr,
+    ) -> Result<()> {
+        if self.get(key)?.as_deref() != expected {
+            return Err(ActionKvError::Conflict { key: key.to_vec() });
+        }
+
+        self.insert(key, new)
     }
 
-    fn process_record<R: Read>(f: &mut R) -> std::io::Result<KeyValuePair> {
-        let saved_checksum = f.read_u32::<LittleEndian>()?;
-        let saved_key_len = f.read_u32::<LittleEndian>()?;
-        let saved_value_len = f.read_u32::<LittleEndian>()?;
-        let data_len = saved_key_len + saved_value_len;
+    /// Inserts `value` only if `key` is missing, failing with `ActionKvError::Conflict`
+    /// otherwise
+    pub fn insert_if_absent(&mut self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.compare_and_swap(key, None, value)
+    }
 
-        let mut data = ByteString::with_capacity(data_len as usize);
-        {
-            f.by_ref().take(data_len as u64).read_to_end(&mut data)?;
+    /// Runs `f` as an optimistic transaction, committing its writes as one batch if it
+    /// returns `Ok`
+    ///
+    /// Nothing is written if `f` fails. See `Transaction` for how reads are validated; with
+    /// exclusive access to the store they can only conflict with the transaction's own
+    /// writes, so this mostly matters for `SharedActionKV::transaction`.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<T>,
+    {
+        let mut txn = Transaction::new(self);
+        let result = f(&mut txn)?;
+        let (reads, writes) = txn.into_parts();
+        self.commit(reads, writes)?;
+
+        Ok(result)
+    }
+
+    /// Writes `writes` as a batch, provided every key in `reads` still has the sequence
+    /// number it was read with
+    pub(crate) fn commit(&mut self, reads: ReadSet, writes: WriteBatch) -> Result<()> {
+        for (key, seq) in reads {
+            let current = self.get_with_meta(&key)?.map(|kv| kv.seq);
+            if current != seq {
+                return Err(ActionKvError::Conflict { key });
+            }
+        }
+
+        self.write_batch(writes)
+    }
+
+    /// Removes a key from the store by appending a tombstone record for it
+    pub fn delete(&mut self, key: &ByteStr) -> Result<()> {
+        self.append_record(key, None, Compression::None, 0)?;
+
+        self.index.remove(key);
+        Ok(())
+    }
+
+    /// Decodes the record starting at `position` in a segment `len` bytes long, as
+    /// `process_record` does
+    ///
+    /// A record streamed in by `insert_from_reader` only has its checksum filled in once the
+    /// whole value has been written. One that is the last in the segment and still has a
+    /// checksum of 0 was interrupted before that, so it is reported as
+    /// `ActionKvError::Truncated`, like any other torn write, rather than as corruption.
+    pub(crate) fn read_record<R: Read + Seek>(
+        f: &mut R,
+        position: u64,
+        len: u64,
+        checksum: ChecksumKind,
+        decryptor: Decryptor,
+        values: bool,
+    ) -> Result<Record> {
+        match ActionKV::process_record(f, position, checksum, decryptor, values) {
+            Err(ActionKvError::Corruption { expected: 0, .. }) if f.stream_position()? == len => {
+                Err(ActionKvError::Truncated { offset: position })
+            }
+            result => result,
+        }
+    }
+
+    /// Decodes the record starting at `position`, which `f` must already be positioned at
+    ///
+    /// Reaching the end of the log exactly at a record boundary is reported as an
+    /// `UnexpectedEof` I/O error, while running out of data part way through a record is
+    /// reported as `ActionKvError::Truncated`.
+    ///
+    /// Values are decrypted with `decryptor` and decompressed. Without `values`, values
+    /// are checked against the record's checksum but not kept, and come back empty; this is
+    /// all `load` needs, and keeps large values out of memory.
+    fn process_record<R: Read>(
+        f: &mut R,
+        position: u64,
+        checksum: ChecksumKind,
+        decryptor: Decryptor,
+        values: bool,
+    ) -> Result<Record> {
+        let mut header = ByteString::with_capacity(RECORD_HEADER_LEN as usize);
+        f.by_ref()
+            .take(RECORD_HEADER_LEN)
+            .read_to_end(&mut header)?;
+        if header.is_empty() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        if header.len() as u64 != RECORD_HEADER_LEN {
+            return Err(ActionKvError::Truncated { offset: position });
+        }
+
+        let (saved_checksum, header) = RecordHeader::decode(&header, position)?;
+        let is_batch = header.is_batch();
+        let is_tombstone = header.is_tombstone();
+
+        let mut hasher = header.hasher(checksum);
+        let mut key = ByteString::new();
+        let mut value = ByteString::new();
+        let complete = if is_batch {
+            ActionKV::read_data(f, header.value_len, &mut hasher, Some(&mut value))?
+        } else {
+            ActionKV::read_data(f, header.key_len as u64, &mut hasher, Some(&mut key))?
+                && (is_tombstone
+                    || ActionKV::read_data(
+                        f,
+                        header.value_len,
+                        &mut hasher,
+                        values.then_some(&mut value),
+                    )?)
         };
-        debug_assert_eq!(data.len(), data_len as usize);
+        if !complete {
+            return Err(ActionKvError::Truncated { offset: position });
+        }
 
-        let checksum = CRC32.checksum(&data);
-        if checksum != saved_checksum {
-            panic!(
-                "data corruption encountered: ({:08x} != {:08x})",
-                checksum, saved_checksum
-            );
+        let actual = hasher.finish();
+        if actual != saved_checksum {
+            return Err(ActionKvError::Corruption {
+                offset: position,
+                expected: saved_checksum,
+                actual,
+            });
         }
 
-        let value = data.split_off(saved_key_len as usize);
-        let key = data;
+        if is_batch {
+            let mut records = Vec::new();
+            let mut payload = &value[..];
+            let mut offset = position + RECORD_HEADER_LEN;
+            while !payload.is_empty() {
+                let remaining = payload.len();
+                let record =
+                    ActionKV::process_record(&mut payload, offset, checksum, decryptor, values);
+                // The payload passed its checksum, so it can't have been cut short by a torn
+                // write, and mustn't be mistaken for one
+                let record = match record {
+                    Err(ActionKvError::Truncated { .. }) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("partial record in batch at offset {}", position),
+                        )
+                        .into())
+                    }
+                    record => record?,
+                };
+                let record = match record {
+                    Record::Batch(..) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("nested batch in record at offset {}", position),
+                        )
+                        .into())
+                    }
+                    record => record,
+                };
+                records.push((offset, record));
+                offset += (remaining - payload.len()) as u64;
+            }
+            return Ok(Record::Batch(records, header));
+        }
+
+        if is_tombstone {
+            return Ok(Record::Tombstone(key, header));
+        }
+
+        if values {
+            value = ActionKV::decode_value(&header, decryptor, position, &key, value)?;
+        }
+        Ok(Record::Value(KeyValuePair {
+            key,
+            value,
+            expires_at: record::to_system_time(header.expires_at),
+            seq: header.seq,
+            timestamp: record::to_system_time(header.timestamp),
+        }))
+    }
+
+    /// Undoes the encryption and compression described by `header` on the value read from
+    /// the record at `offset`
+    pub(crate) fn decode_value(
+        header: &RecordHeader,
+        decryptor: Decryptor,
+        offset: u64,
+        key: &ByteStr,
+        value: ByteString,
+    ) -> Result<ByteString> {
+        let value = decryptor.decrypt(offset, header, key, value)?;
+        Ok(compression::decompress(header.flags, value)?)
+    }
+
+    /// Feeds the next `len` bytes of `f` through `hasher`, appending them to `out` if given
+    ///
+    /// The data is read a chunk at a time, so a length damaged on disk can't set off a huge
+    /// allocation. Returns `false` if `f` runs out first.
+    fn read_data<R: Read>(
+        f: &mut R,
+        len: u64,
+        hasher: &mut Hasher,
+        mut out: Option<&mut ByteString>,
+    ) -> std::io::Result<bool> {
+        let mut buf = [0; 8 * 1024];
+        let mut remaining = len;
+        while remaining > 0 {
+            let wanted = remaining.min(buf.len() as u64) as usize;
+            let n = match f.read(&mut buf[..wanted]) {
+                Ok(0) => return Ok(false),
+                Ok(n) => n,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            hasher.update(&buf[..n]);
+            if let Some(out) = out.as_deref_mut() {
+                out.extend_from_slice(&buf[..n]);
+            }
+            remaining -= n as u64;
+        }
 
-        Ok(KeyValuePair { key, value })
+        Ok(true)
+    }
+}
+
+impl Drop for ActionKV {
+    /// Syncs outstanding writes on a clean shutdown unless the store never syncs, and
+    /// refreshes the hint file if one is kept
+    fn drop(&mut self) {
+        if self.unsynced_writes > 0 && self.options.sync != SyncPolicy::Never {
+            let _ = self.sync();
+        }
+        if self.options.hint_file && self.loaded && self.hint_dirty {
+            let _ = self.write_hint();
+        }
     }
 }
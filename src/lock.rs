@@ -0,0 +1,67 @@
+//! Advisory lock keeping more than one writer out of a store directory
+//!
+//! Every open store holds a lock on the `LOCK` file in its directory: an exclusive one to
+//! write, or a shared one when opened with `Options::read_only`. Locks are advisory, so they
+//! only keep out other `ActionKV` handles, whether in this process or another, and are
+//! released by the operating system when the handle is dropped or the process dies.
+//!
+//! A reader only needs to read `LOCK`, so it can share a lock on a store it has no write
+//! access to. If `LOCK` doesn't exist yet and can't be created, on a read-only filesystem
+//! say, the reader locks the directory itself instead; so that it still keeps writers out,
+//! on Unix a writer locks the directory as well as `LOCK`.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+use crate::{ActionKvError, Result};
+
+const LOCK_FILE_NAME: &str = "LOCK";
+
+/// Locks held on a store directory, released when dropped
+#[derive(Debug)]
+pub(crate) struct Lock {
+    _files: Vec<File>,
+}
+
+/// Locks the store in `dir`, shared or exclusively, failing with `ActionKvError::Locked`
+/// rather than waiting if another handle is in the way
+pub(crate) fn acquire(dir: &Path, shared: bool) -> Result<Lock> {
+    let path = dir.join(LOCK_FILE_NAME);
+    if shared {
+        let f = match File::open(&path) {
+            Ok(f) => f,
+            Err(_) => create(&path).or_else(|_| File::open(dir))?,
+        };
+        return Ok(Lock {
+            _files: vec![try_lock(f, true)?],
+        });
+    }
+
+    let mut files = vec![try_lock(create(&path)?, false)?];
+    #[cfg(unix)]
+    files.push(try_lock(File::open(dir)?, false)?);
+
+    Ok(Lock { _files: files })
+}
+
+fn create(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+fn try_lock(f: File, shared: bool) -> Result<File> {
+    let locked = if shared {
+        f.try_lock_shared()
+    } else {
+        f.try_lock()
+    };
+    match locked {
+        Ok(()) => Ok(f),
+        Err(TryLockError::WouldBlock) => Err(ActionKvError::Locked),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use crate::{ChecksumKind, Compression, EncryptionKey, IndexKind, MAX_VALUE_SIZE};
+
+/// Settings used when opening an `ActionKV` store with `ActionKV::open_with`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// When appended records are flushed to stable storage
+    pub sync: SyncPolicy,
+    /// How damaged records are handled when scanning the log
+    pub recovery: RecoveryPolicy,
+    /// Whether `load` cuts off a partially written record left at the end of the log
+    ///
+    /// A record appended after a torn one would otherwise be swallowed by the torn record's
+    /// lengths and become unreadable, so this is enabled by default.
+    pub truncate_torn_tail: bool,
+    /// Whether to keep a hint file next to the log for faster startup
+    ///
+    /// `load` seeds the index from the hint when it is still valid and falls back to a full
+    /// scan otherwise. The hint is rewritten on compaction and when the store is dropped.
+    pub hint_file: bool,
+    /// Size in bytes past which a new segment is started rather than appending to the
+    /// current one
+    pub max_segment_size: u64,
+    /// Data structure used for the in-memory index
+    pub index: IndexKind,
+    /// Largest key in bytes that can be written; capped at `MAX_KEY_SIZE`
+    pub max_key_size: u64,
+    /// Largest value in bytes that can be written; capped at `MAX_VALUE_SIZE`
+    pub max_value_size: u64,
+    /// Algorithm used to checksum records written from now on
+    pub checksum: ChecksumKind,
+    /// How values are compressed when they are written; see `ActionKV::insert_compressed` to
+    /// override it for a single value
+    pub compression: Compression,
+    /// Key to encrypt values with; `None` stores them in plaintext
+    ///
+    /// Keys themselves are never encrypted, as the index is built from them. Values written
+    /// before a key was set stay readable, in plaintext, until they are overwritten.
+    pub encryption_key: Option<EncryptionKey>,
+    /// Refuses values stored in plaintext once `encryption_key` is set, failing reads of them
+    /// with `ActionKvError::Decryption`
+    ///
+    /// Without this, anyone able to write to the log can swap an encrypted value for one in
+    /// plaintext. Turn it on once every value written before the key was set has been
+    /// overwritten, or compacted away.
+    pub require_encryption: bool,
+    /// Opens the store for reading only, under a lock shared with other read-only handles
+    ///
+    /// The lock file is only read, so a store can be opened this way without write access to
+    /// it. Nothing in the directory is changed beyond creating the lock file if it is missing
+    /// and the directory is writable; if it isn't, the directory itself is locked instead.
+    /// Writes fail with `ActionKvError::ReadOnly`, `load` leaves damaged tails in place, and a
+    /// missing directory or segments in an older format are refused rather than created or
+    /// upgraded.
+    pub read_only: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            sync: SyncPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            truncate_torn_tail: true,
+            hint_file: false,
+            max_segment_size: 64 * 1024 * 1024,
+            index: IndexKind::default(),
+            max_key_size: 64 * 1024,
+            max_value_size: MAX_VALUE_SIZE,
+            checksum: ChecksumKind::default(),
+            compression: Compression::default(),
+            encryption_key: None,
+            require_encryption: false,
+            read_only: false,
+        }
+    }
+}
+
+/// Controls how often `ActionKV` calls `fsync` on the log after appending to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Leave it to the operating system; only `ActionKV::sync` forces data to disk
+    #[default]
+    Never,
+    /// Sync after every write, so a successful write survives a crash
+    EveryWrite,
+    /// Sync once every `n` writes
+    EveryNWrites(u32),
+    /// Sync as part of the first write made once the interval has elapsed since the last sync
+    ///
+    /// This is not a timer: nothing syncs between writes, so the writes made just before the
+    /// store goes quiet stay unsynced until the next write, a call to `ActionKV::sync`, or the
+    /// store being dropped. It bounds how often syncs happen, not how long data can go unsynced.
+    OnWriteAfter(Duration),
+}
+
+/// How `load` and `find` react to a record that fails its checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryPolicy {
+    /// Stop and return `ActionKvError::Corruption`
+    #[default]
+    Fail,
+    /// Ignore the damaged record and carry on with the next one
+    Skip,
+    /// Treat the damaged record as the end of the log; `load` also truncates the file there
+    Truncate,
+}
@@ -0,0 +1,155 @@
+use std::io::{Cursor, Read};
+
+use crate::checksum::Hasher;
+use crate::compression;
+use crate::encryption::{Decryptor, FLAG_ENCRYPTED};
+use crate::record::RecordHeader;
+use crate::segment::PositionalReader;
+use crate::{ActionKV, ActionKvError, ByteStr, ByteString, Result};
+
+/// Streams a value out of the log, as returned by `ActionKV::get_reader`
+///
+/// A record's checksum covers both its key and value, so it can only be checked once the whole
+/// value has been read. The read that reaches the end of the value fails with an
+/// `InvalidData` error wrapping `ActionKvError::Corruption` if it doesn't match, as does every
+/// read after it.
+///
+/// Compressed or encrypted values can't be streamed; they are read, checked and decoded in
+/// full before the reader is returned.
+pub struct ValueReader<'a> {
+    pub(crate) key: ByteString,
+    /// Checksum stored in the record header
+    pub(crate) checksum: u64,
+    pub(crate) header: RecordHeader,
+    source: Source<'a>,
+}
+
+enum Source<'a> {
+    /// The value as stored in the log, read straight from its segment
+    Stored {
+        f: PositionalReader<'a>,
+        /// Offset of the record within its segment
+        offset: u64,
+        remaining: u64,
+        hasher: Hasher,
+    },
+    /// A value that has already been read back and decompressed
+    Decoded(Cursor<ByteString>),
+}
+
+impl<'a> ValueReader<'a> {
+    /// Wraps `f`, positioned at the start of a value, given the checksum of the record so far
+    pub(crate) fn new(
+        f: PositionalReader<'a>,
+        offset: u64,
+        key: ByteString,
+        checksum: u64,
+        header: RecordHeader,
+        hasher: Hasher,
+    ) -> Self {
+        Self {
+            key,
+            checksum,
+            header,
+            source: Source::Stored {
+                f,
+                offset,
+                remaining: header.value_len,
+                hasher,
+            },
+        }
+    }
+
+    /// Decrypts and decompresses the value up front if it was stored that way, so that reads
+    /// return the value as it was written
+    pub(crate) fn decoded(mut self, decryptor: Decryptor) -> Result<Self> {
+        let offset = match &self.source {
+            Source::Stored { offset, .. } => *offset,
+            Source::Decoded(_) => return Ok(self),
+        };
+        let flags = self.header.flags;
+        if flags & FLAG_ENCRYPTED == 0 {
+            decryptor.check_plaintext(offset)?;
+            if !compression::is_compressed(flags) {
+                return Ok(self);
+            }
+        }
+
+        let mut stored = ByteString::new();
+        self.read_to_end(&mut stored)?;
+        let value = ActionKV::decode_value(&self.header, decryptor, offset, &self.key, stored)?;
+        self.source = Source::Decoded(Cursor::new(value));
+
+        Ok(self)
+    }
+
+    pub fn key(&self) -> &ByteStr {
+        &self.key
+    }
+
+    /// Length of the whole value in bytes
+    pub fn len(&self) -> u64 {
+        match &self.source {
+            Source::Stored { .. } => self.header.value_len,
+            Source::Decoded(value) => value.get_ref().len() as u64,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn verify(&self) -> std::io::Result<()> {
+        let (offset, hasher) = match &self.source {
+            Source::Stored { offset, hasher, .. } => (*offset, hasher),
+            Source::Decoded(_) => return Ok(()),
+        };
+
+        let actual = hasher.clone().finish();
+        if actual != self.checksum {
+            let err = ActionKvError::Corruption {
+                offset,
+                expected: self.checksum,
+                actual,
+            };
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for ValueReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let (f, offset, remaining, hasher) = match &mut self.source {
+            Source::Stored {
+                f,
+                offset,
+                remaining,
+                hasher,
+                ..
+            } => (f, *offset, remaining, hasher),
+            Source::Decoded(value) => return value.read(buf),
+        };
+
+        if *remaining == 0 {
+            self.verify()?;
+            return Ok(0);
+        }
+
+        let wanted = (*remaining).min(buf.len() as u64) as usize;
+        let n = f.read(&mut buf[..wanted])?;
+        if n == 0 && wanted > 0 {
+            let err = ActionKvError::Truncated { offset };
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, err));
+        }
+
+        hasher.update(&buf[..n]);
+        *remaining -= n as u64;
+        if *remaining == 0 {
+            self.verify()?;
+        }
+
+        Ok(n)
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::checksum::Hasher;
+use crate::{ActionKvError, ChecksumKind, Result, BATCH, CRC32, TOMBSTONE};
+
+/// Size of the <checksum u64><flags u8><key_len u32><value_len u64><expires_at u64><seq u64>
+/// <timestamp u64><header_checksum u32> header preceding every record
+///
+/// The checksum covers everything after it apart from the header checksum: the rest of the
+/// header, then the key and value. The header checksum is a CRC32 of the fields between the
+/// two, so that the lengths can be trusted before any data is read with them. It leaves the
+/// first checksum out, which is only filled in once a streamed value has been written.
+pub(crate) const RECORD_HEADER_LEN: u64 = 49;
+
+/// Span of the fields covered by the header checksum
+const FIELDS: std::ops::Range<usize> = 8..45;
+
+/// The fields of a record header that follow its checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct RecordHeader {
+    /// How the value was compressed and whether it was encrypted
+    pub(crate) flags: u8,
+    pub(crate) key_len: u32,
+    /// Length of the value as stored, i.e. after compression and encryption
+    pub(crate) value_len: u64,
+    /// Milliseconds since the Unix epoch from which the value counts as missing, or 0 if it
+    /// never expires
+    pub(crate) expires_at: u64,
+    /// Position of the write in the order of every write to the store, starting from 1; 0
+    /// for records upgraded from before sequence numbers, and for batch envelopes other
+    /// than the empty one a merge leaves to carry the latest sequence number
+    pub(crate) seq: u64,
+    /// Milliseconds since the Unix epoch at which the record was written, or 0 if unknown
+    pub(crate) timestamp: u64,
+}
+
+impl RecordHeader {
+    pub(crate) fn new(key_len: u32, value_len: u64) -> Self {
+        Self {
+            flags: 0,
+            key_len,
+            value_len,
+            ..Self::default()
+        }
+    }
+
+    /// Splits a header read back from the log, for the record at `offset`, into its
+    /// checksum and the rest
+    ///
+    /// Fails with `ActionKvError::Corruption` if the header checksum doesn't match, in which
+    /// case nothing in the header can be relied on, least of all the record's length.
+    pub(crate) fn decode(buf: &[u8], offset: u64) -> Result<(u64, Self)> {
+        let mut fields = &buf[FIELDS.end..];
+        let expected = fields.read_u32::<LittleEndian>()?;
+        let actual = CRC32.checksum(&buf[FIELDS]);
+        if actual != expected {
+            return Err(ActionKvError::Corruption {
+                offset,
+                expected: expected as u64,
+                actual: actual as u64,
+            });
+        }
+
+        let mut buf = buf;
+        let checksum = buf.read_u64::<LittleEndian>()?;
+        let header = Self {
+            flags: buf.read_u8()?,
+            key_len: buf.read_u32::<LittleEndian>()?,
+            value_len: buf.read_u64::<LittleEndian>()?,
+            expires_at: buf.read_u64::<LittleEndian>()?,
+            seq: buf.read_u64::<LittleEndian>()?,
+            timestamp: buf.read_u64::<LittleEndian>()?,
+        };
+
+        Ok((checksum, header))
+    }
+
+    pub(crate) fn write<W: Write>(&self, f: &mut W, checksum: u64) -> std::io::Result<()> {
+        let mut buf = Vec::with_capacity(RECORD_HEADER_LEN as usize);
+        buf.write_u64::<LittleEndian>(checksum)?;
+        buf.write_u8(self.flags)?;
+        buf.write_u32::<LittleEndian>(self.key_len)?;
+        buf.write_u64::<LittleEndian>(self.value_len)?;
+        buf.write_u64::<LittleEndian>(self.expires_at)?;
+        buf.write_u64::<LittleEndian>(self.seq)?;
+        buf.write_u64::<LittleEndian>(self.timestamp)?;
+        let header_checksum = CRC32.checksum(&buf[FIELDS]);
+        buf.write_u32::<LittleEndian>(header_checksum)?;
+
+        f.write_all(&buf)
+    }
+
+    /// Starts the record's checksum, ready to be fed its data
+    pub(crate) fn hasher(&self, checksum: ChecksumKind) -> Hasher {
+        let mut hasher = checksum.hasher();
+        hasher.update(&[self.flags]);
+        hasher.update(&self.key_len.to_le_bytes());
+        hasher.update(&self.value_len.to_le_bytes());
+        hasher.update(&self.expires_at.to_le_bytes());
+        hasher.update(&self.seq.to_le_bytes());
+        hasher.update(&self.timestamp.to_le_bytes());
+        hasher
+    }
+
+    pub(crate) fn is_batch(&self) -> bool {
+        self.key_len == BATCH
+    }
+
+    pub(crate) fn is_tombstone(&self) -> bool {
+        !self.is_batch() && self.value_len == TOMBSTONE
+    }
+
+    /// Number of bytes of data following the header
+    pub(crate) fn data_len(&self) -> u64 {
+        if self.is_batch() {
+            self.value_len
+        } else if self.is_tombstone() {
+            self.key_len as u64
+        } else {
+            self.key_len as u64 + self.value_len
+        }
+    }
+
+    /// Whether the value has expired as of `now`, in milliseconds since the Unix epoch
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        self.expires_at != 0 && self.expires_at <= now
+    }
+}
+
+/// The current time in milliseconds since the Unix epoch
+pub(crate) fn now_millis() -> u64 {
+    to_millis(SystemTime::now())
+}
+
+/// Converts `time` to milliseconds since the Unix epoch, the form times take in record
+/// headers
+pub(crate) fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Converts a time stored in a record header to a `SystemTime`, with 0 meaning none
+pub(crate) fn to_system_time(millis: u64) -> Option<SystemTime> {
+    (millis != 0).then(|| UNIX_EPOCH + Duration::from_millis(millis))
+}
@@ -0,0 +1,192 @@
+//! Salvaging what can be read from damaged segments
+//!
+//! `load` can step over a record that fails its checksum, but only if the record's lengths
+//! survived; otherwise it loses its place in the segment, and everything after the damage
+//! with it. Repair instead searches forward a byte at a time for the next offset at which a
+//! complete record passes its checksum, and rewrites the segment with every record found
+//! that way.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::encryption::Decryptor;
+use crate::header::{Header, FORMAT_VERSION, HEADER_LEN};
+use crate::record::{RecordHeader, RECORD_HEADER_LEN};
+use crate::segment::{self, Segment};
+use crate::{hint, lock, ActionKV, ActionKvError, Result};
+
+/// What `ActionKV::repair` salvaged from a store directory
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepairReport {
+    pub dir: PathBuf,
+    /// Segments that were rewritten, or left alone because they couldn't be scanned
+    pub segments: Vec<SegmentRepair>,
+    /// Records kept from the damaged segments, counting a batch as one
+    pub recovered: u64,
+    /// Bytes dropped from the damaged segments
+    pub lost_bytes: u64,
+}
+
+/// What `ActionKV::repair` did with a damaged segment
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SegmentRepair {
+    pub id: u32,
+    pub path: PathBuf,
+    /// Copy of the segment as it was before it was rewritten
+    pub backup: Option<PathBuf>,
+    /// Why the segment was left alone: its header couldn't be read, or it is in an older
+    /// format, which opening the store upgrades
+    pub skipped: Option<String>,
+    pub recovered: u64,
+    pub lost: Vec<LostRegion>,
+}
+
+/// A stretch of a segment in which no valid record could be found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LostRegion {
+    pub offset: u64,
+    pub bytes: u64,
+}
+
+pub(crate) fn repair(dir: &Path) -> Result<RepairReport> {
+    let _lock = lock::acquire(dir, false)?;
+    let (found, _) = segment::find(dir)?;
+    let mut report = RepairReport {
+        dir: dir.to_path_buf(),
+        ..RepairReport::default()
+    };
+
+    for (&id, &first) in &found {
+        let path = dir.join(Segment::file_name(first, id));
+        if let Some(segment) = repair_segment(dir, path, id)? {
+            report.recovered += segment.recovered;
+            report.lost_bytes += segment.lost.iter().map(|lost| lost.bytes).sum::<u64>();
+            report.segments.push(segment);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Rewrites the segment at `path` without its damaged regions, returning `None` if it had
+/// none
+///
+/// The original is copied to `<name>.corrupt` first, and the rewritten segment renamed over
+/// it once synced, so a crash leaves either the original or the repaired segment in place.
+fn repair_segment(dir: &Path, path: PathBuf, id: u32) -> Result<Option<SegmentRepair>> {
+    let data = std::fs::read(&path)?;
+    let mut report = SegmentRepair {
+        id,
+        path,
+        ..SegmentRepair::default()
+    };
+
+    let header = match Header::read(&mut &data[..]) {
+        Ok(Some(header)) if header.version == FORMAT_VERSION => header,
+        Ok(Some(Header { version, .. })) => {
+            report.skipped = Some(format!("format version {} needs upgrading", version));
+            return Ok(Some(report));
+        }
+        Ok(None) => {
+            report.skipped = Some("format version 0 needs upgrading".to_string());
+            return Ok(Some(report));
+        }
+        Err(err) => {
+            report.skipped = Some(err.to_string());
+            return Ok(Some(report));
+        }
+    };
+
+    // Ranges of `data` holding intact records, in order
+    let mut kept = Vec::new();
+    let mut pos = HEADER_LEN as usize;
+    let mut lost_from = None;
+    while pos < data.len() {
+        let mut record = &data[pos..];
+        let result = if fits(record) {
+            ActionKV::process_record(
+                &mut record,
+                pos as u64,
+                header.checksum,
+                Decryptor::default(),
+                false,
+            )
+        } else {
+            Err(ActionKvError::Truncated { offset: pos as u64 })
+        };
+        match result {
+            Ok(_) => {
+                let end = data.len() - record.len();
+                if let Some(offset) = lost_from.take() {
+                    report.lost.push(LostRegion {
+                        offset: offset as u64,
+                        bytes: (pos - offset) as u64,
+                    });
+                }
+                kept.push(pos..end);
+                pos = end;
+            }
+            Err(
+                ActionKvError::Corruption { .. }
+                | ActionKvError::Truncated { .. }
+                | ActionKvError::Io(_),
+            ) => {
+                lost_from.get_or_insert(pos);
+                pos += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if let Some(offset) = lost_from {
+        report.lost.push(LostRegion {
+            offset: offset as u64,
+            bytes: (data.len() - offset) as u64,
+        });
+    }
+
+    if report.lost.is_empty() {
+        return Ok(None);
+    }
+    report.recovered = kept.len() as u64;
+
+    let backup = report.path.with_extension("log.corrupt");
+    std::fs::write(&backup, &data)?;
+    File::open(&backup)?.sync_all()?;
+
+    let tmp_path = report.path.with_extension("log.tmp");
+    let tmp = File::create(&tmp_path)?;
+    {
+        let mut dst = BufWriter::new(&tmp);
+        dst.write_all(&data[..HEADER_LEN as usize])?;
+        for range in kept {
+            dst.write_all(&data[range])?;
+        }
+        dst.flush()?;
+    }
+    tmp.sync_all()?;
+    drop(tmp);
+
+    hint::remove(dir)?;
+    std::fs::rename(&tmp_path, &report.path)?;
+    ActionKV::sync_dir(dir)?;
+    report.backup = Some(backup);
+
+    Ok(Some(report))
+}
+
+/// Whether `data` starts with an intact record header whose data would fit in what follows it
+///
+/// Checked before each candidate offset is tried in full, so that only offsets with a valid
+/// header checksum are checksummed any further.
+fn fits(data: &[u8]) -> bool {
+    let header_len = RECORD_HEADER_LEN as usize;
+    if data.len() < header_len {
+        return false;
+    }
+
+    RecordHeader::decode(&data[..header_len], 0)
+        .is_ok_and(|(_, header)| header.data_len() <= (data.len() - header_len) as u64)
+}
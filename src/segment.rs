@@ -0,0 +1,352 @@
+//! Numbered log files that together make up a store directory
+//!
+//! Records are appended to the newest segment until it reaches `Options::max_segment_size`,
+//! at which point a new one is started and the old one becomes immutable. A segment is named
+//! after its id, e.g. `00000007.log`. Merging a run of immutable segments produces a single
+//! file named after the range it replaces, e.g. `00000001-00000006.log`, which takes the id
+//! of the newest segment in the range so that it still sorts before everything written
+//! after it.
+//!
+//! Every segment starts with a `Header` recording its format version; see `header`.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::header::{Header, FORMAT_VERSION, HEADER_LEN};
+use crate::upgrade::upgrade;
+use crate::{ActionKV, ActionKvError, ChecksumKind, Result};
+
+const EXTENSION: &str = "log";
+
+#[derive(Debug)]
+pub(crate) struct Segment {
+    /// Id of the oldest segment merged into this one; equal to `id` for unmerged segments
+    pub(crate) first: u32,
+    pub(crate) id: u32,
+    pub(crate) path: PathBuf,
+    pub(crate) f: File,
+    /// Size of the file, header included
+    pub(crate) len: u64,
+    pub(crate) header: Header,
+}
+
+/// Details of one of the files making up a store, as returned by `ActionKV::segments`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub id: u32,
+    /// Id of the oldest segment merged into this one; equal to `id` for unmerged segments
+    pub first: u32,
+    pub path: PathBuf,
+    /// Size of the file in bytes, header included
+    pub len: u64,
+    pub format_version: u16,
+    pub created_at: SystemTime,
+}
+
+impl Segment {
+    pub(crate) fn file_name(first: u32, id: u32) -> String {
+        if first == id {
+            format!("{:08}.{}", id, EXTENSION)
+        } else {
+            format!("{:08}-{:08}.{}", first, id, EXTENSION)
+        }
+    }
+
+    /// Opens an existing segment, or creates it if `writable` is set
+    ///
+    /// Writable segments are opened for appending; there is only ever one, the newest. A new
+    /// segment is given a header straight away, and one written in an older format is
+    /// upgraded to the current one before it is opened. Either way its records are
+    /// checksummed with `checksum`.
+    pub(crate) fn open(
+        dir: &Path,
+        first: u32,
+        id: u32,
+        writable: bool,
+        checksum: ChecksumKind,
+    ) -> Result<Self> {
+        let path = dir.join(Segment::file_name(first, id));
+        let mut f = OpenOptions::new()
+            .read(true)
+            .create(writable)
+            .append(writable)
+            .open(&path)?;
+        let mut len = f.metadata()?.len();
+
+        let mut reader = PositionalReader {
+            f: &f,
+            offset: 0,
+            len,
+        };
+        let header = match Header::read(&mut reader) {
+            Ok(Some(header)) if header.version == FORMAT_VERSION => header,
+            Ok(Some(header)) => {
+                drop(f);
+                upgrade(
+                    dir,
+                    &path,
+                    header.version,
+                    header.checksum,
+                    checksum,
+                    writable,
+                )?;
+                return Segment::open(dir, first, id, writable, checksum);
+            }
+            Ok(None) if len == 0 && writable => {
+                let header = Header::new(checksum);
+                f.write_all(&header.encode())?;
+                len = HEADER_LEN;
+                header
+            }
+            Ok(None) => {
+                drop(f);
+                upgrade(dir, &path, 0, ChecksumKind::Crc32, checksum, writable)?;
+                return Segment::open(dir, first, id, writable, checksum);
+            }
+            // Records are only ever appended after a complete header, so a new segment whose
+            // header write was cut short holds nothing and can be started over
+            Err(ActionKvError::Truncated { .. }) if writable => {
+                f.set_len(0)?;
+                let header = Header::new(checksum);
+                f.write_all(&header.encode())?;
+                len = HEADER_LEN;
+                header
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            first,
+            id,
+            path,
+            f,
+            len,
+            header,
+        })
+    }
+
+    /// Whether the segment at `path` is in an older format, which `open` would upgrade
+    pub(crate) fn needs_upgrade(path: &Path) -> Result<bool> {
+        let mut f = File::open(path)?;
+        match Header::read(&mut f)? {
+            Some(header) => Ok(header.version != FORMAT_VERSION),
+            None => Ok(true),
+        }
+    }
+
+    /// Whether the segment holds no records
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len <= HEADER_LEN
+    }
+
+    pub(crate) fn info(&self) -> SegmentInfo {
+        SegmentInfo {
+            id: self.id,
+            first: self.first,
+            path: self.path.clone(),
+            len: self.len,
+            format_version: self.header.version,
+            created_at: self.header.created_at(),
+        }
+    }
+
+    /// Returns a reader over the segment, positioned at its first record, that doesn't share
+    /// the file's cursor
+    ///
+    /// The reader ends at `len`, so it never sees data written after the segment was last
+    /// brought up to date.
+    pub(crate) fn reader(&self) -> PositionalReader<'_> {
+        PositionalReader {
+            f: &self.f,
+            offset: HEADER_LEN,
+            len: self.len,
+        }
+    }
+
+    /// Opens a second handle on the segment as it stands, for a snapshot
+    pub(crate) fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            first: self.first,
+            id: self.id,
+            path: self.path.clone(),
+            f: self.f.try_clone()?,
+            len: self.len,
+            header: self.header,
+        })
+    }
+
+    /// Reopens the segment read-only once it is no longer the one being appended to
+    pub(crate) fn seal(&mut self) -> std::io::Result<()> {
+        self.f.sync_all()?;
+        self.f = File::open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Reads a file through positional reads (`pread`), keeping its own offset
+///
+/// Any number of these can read from the same `File` at once, as they never move the
+/// file's cursor.
+pub(crate) struct PositionalReader<'a> {
+    f: &'a File,
+    offset: u64,
+    /// Offset at which the reader treats the file as ending
+    len: u64,
+}
+
+impl Read for PositionalReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.len.saturating_sub(self.offset);
+        let wanted = (buf.len() as u64).min(available) as usize;
+        if wanted == 0 {
+            return Ok(0);
+        }
+
+        let n = read_at(self.f, &mut buf[..wanted], self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for PositionalReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => {
+                self.offset = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(delta) => (self.offset, delta),
+            SeekFrom::End(delta) => (self.len, delta),
+        };
+
+        self.offset = base.checked_add_signed(delta).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.offset)
+    }
+}
+
+#[cfg(unix)]
+fn read_at(f: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(f, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(f: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(f, buf, offset)
+}
+
+/// Parses `00000007.log` into `(7, 7)` and `00000001-00000006.log` into `(1, 6)`
+fn parse_file_name(name: &str) -> Option<(u32, u32)> {
+    let stem = name.strip_suffix(EXTENSION)?.strip_suffix('.')?;
+    let (first, id) = match stem.split_once('-') {
+        Some((first, id)) => (first.parse().ok()?, id.parse().ok()?),
+        None => {
+            let id = stem.parse().ok()?;
+            (id, id)
+        }
+    };
+
+    if first > id {
+        return None;
+    }
+    Some((first, id))
+}
+
+/// Lists the segments in `dir` as `id -> first`, oldest first
+///
+/// Anything left behind by an interrupted merge is removed: segments whose range is covered
+/// by a merged segment, which must have been fully written before it was renamed into place,
+/// and partially written merge output.
+pub(crate) fn discover(dir: &Path) -> std::io::Result<BTreeMap<u32, u32>> {
+    let (segments, leftovers) = find(dir)?;
+    for path in leftovers {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(segments)
+}
+
+/// Lists the segments in `dir` like `discover`, along with the leftovers of an interrupted
+/// merge, without touching anything
+pub(crate) fn find(dir: &Path) -> std::io::Result<(BTreeMap<u32, u32>, Vec<PathBuf>)> {
+    let mut found = Vec::new();
+    let mut leftovers = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if name.ends_with(".tmp") && parse_file_name(name.trim_end_matches(".tmp")).is_some() {
+            leftovers.push(entry.path());
+        } else if let Some(range) = parse_file_name(name) {
+            found.push(range);
+        }
+    }
+
+    let mut segments = BTreeMap::new();
+    for &(first, id) in &found {
+        let covered = found
+            .iter()
+            .any(|&(f, i)| (f, i) != (first, id) && f <= first && id <= i);
+        if covered {
+            leftovers.push(dir.join(Segment::file_name(first, id)));
+        } else {
+            segments.insert(id, first);
+        }
+    }
+
+    Ok((segments, leftovers))
+}
+
+/// Moves a store kept in a single file at `path`, as written before stores became
+/// directories, into a directory at the same path, where it becomes the first segment
+///
+/// The file is renamed aside to `<name>.migrating` before the directory is created, so a
+/// move that is interrupted part way is finished the next time the store is opened. Does
+/// nothing if `path` is not a file and no move was left unfinished.
+pub(crate) fn migrate_file(path: &Path) -> std::io::Result<()> {
+    let aside = match path.file_name() {
+        Some(name) => {
+            let mut name = name.to_os_string();
+            name.push(".migrating");
+            path.with_file_name(name)
+        }
+        None => return Ok(()),
+    };
+
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+        std::fs::rename(path, &aside)?;
+    } else if !aside.is_file() {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(path)?;
+    let first = path.join(Segment::file_name(1, 1));
+    if first.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!(
+                "can't move {} into {}, which already holds a store",
+                aside.display(),
+                path.display()
+            ),
+        ));
+    }
+    std::fs::rename(&aside, first)?;
+
+    ActionKV::sync_dir(path)?;
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => ActionKV::sync_dir(parent),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::Read;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use crate::{
+    ActionKV, ByteStr, ByteString, KeyValuePair, LogEntry, Position, Result, Snapshot, Transaction,
+    WriteBatch,
+};
+
+/// A cloneable handle to an `ActionKV` store that can be shared between threads
+///
+/// Reads take a shared lock and use positional reads, so any number of them run at once.
+/// Writes take an exclusive lock, which serializes them and keeps them from running
+/// alongside reads.
+#[derive(Debug, Clone)]
+pub struct SharedActionKV {
+    inner: Arc<RwLock<ActionKV>>,
+}
+
+impl SharedActionKV {
+    /// Wraps a store, which should already have been loaded
+    pub fn new(store: ActionKV) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(store)),
+        }
+    }
+
+    /// Locks the store for reading
+    ///
+    /// A panic while the lock was held doesn't stop the store from being used; every write
+    /// leaves the index consistent with what has reached the log.
+    pub fn read(&self) -> RwLockReadGuard<'_, ActionKV> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the store for writing
+    pub fn write(&self) -> RwLockWriteGuard<'_, ActionKV> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>> {
+        self.read().get(key)
+    }
+
+    pub fn get_with_meta(&self, key: &ByteStr) -> Result<Option<KeyValuePair>> {
+        self.read().get_with_meta(key)
+    }
+
+    pub fn get_at(&self, position: Position) -> Result<KeyValuePair> {
+        self.read().get_at(position)
+    }
+
+    pub fn find(&self, target: &ByteStr) -> Result<Option<(Position, ByteString)>> {
+        self.read().find(target)
+    }
+
+    pub fn history(&self, key: &ByteStr) -> Result<Vec<LogEntry>> {
+        self.read().history(key)
+    }
+
+    pub fn get_as_of(&self, key: &ByteStr, seq: u64) -> Result<Option<ByteString>> {
+        self.read().get_as_of(key, seq)
+    }
+
+    pub fn insert(&self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.write().insert(key, value)
+    }
+
+    pub fn insert_with_ttl(&self, key: &ByteStr, value: &ByteStr, ttl: Duration) -> Result<()> {
+        self.write().insert_with_ttl(key, value, ttl)
+    }
+
+    pub fn insert_from_reader<R: Read>(&self, key: &ByteStr, value: R, len: u64) -> Result<()> {
+        self.write().insert_from_reader(key, value, len)
+    }
+
+    pub fn update(&self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.write().update(key, value)
+    }
+
+    /// Compares and swaps under the write lock, so no other write can come in between; see
+    /// `ActionKV::compare_and_swap`
+    pub fn compare_and_swap(
+        &self,
+        key: &ByteStr,
+        expected: Option<&ByteStr>,
+        new: &ByteStr,
+    ) -> Result<()> {
+        self.write().compare_and_swap(key, expected, new)
+    }
+
+    pub fn insert_if_absent(&self, key: &ByteStr, value: &ByteStr) -> Result<()> {
+        self.write().insert_if_absent(key, value)
+    }
+
+    /// Runs `f` as an optimistic transaction; see `ActionKV::transaction`
+    ///
+    /// `f` runs without holding a lock, taking the read lock for each read, so other threads
+    /// carry on writing meanwhile. The write lock is only taken to validate and commit the
+    /// transaction, which fails with `ActionKvError::Conflict` if another write touched a
+    /// key it read.
+    pub fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<T>,
+    {
+        let mut txn = Transaction::shared(self);
+        let result = f(&mut txn)?;
+        let (reads, writes) = txn.into_parts();
+        self.write().commit(reads, writes)?;
+
+        Ok(result)
+    }
+
+    pub fn delete(&self, key: &ByteStr) -> Result<()> {
+        self.write().delete(key)
+    }
+
+    pub fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        self.write().write_batch(batch)
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.write().sync()
+    }
+
+    pub fn compact(&self) -> Result<()> {
+        self.write().compact()
+    }
+
+    /// Takes a snapshot of the store, holding the read lock only while the index is copied
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.read().snapshot()
+    }
+}
@@ -0,0 +1,83 @@
+use std::ops::RangeBounds;
+
+use crate::{
+    ActionKV, ByteStr, ByteString, Entries, Index, KeyValuePair, LogEntries, LogEntry, Position,
+    Result, SegmentInfo, ValueReader,
+};
+
+/// A read-only view of the store as it stood when `ActionKV::snapshot` was called
+///
+/// The snapshot has its own copy of the index and its own handles on the segment files,
+/// each read no further than its length at the time, so writes made to the store afterwards
+/// never show up in it. It doesn't borrow the store, which can go on being written to, or
+/// even dropped, while the snapshot is in use.
+///
+/// Segments merged away by compaction stay readable through a snapshot taken before, as
+/// their files are only unlinked while it holds them open. On Windows, where open files
+/// can't be removed, compaction fails until the snapshot is dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    /// A store that is only ever read from, set up to match the original at the time
+    store: ActionKV,
+}
+
+impl Snapshot {
+    pub(crate) fn new(store: ActionKV) -> Self {
+        Self { store }
+    }
+
+    pub fn get(&self, key: &ByteStr) -> Result<Option<ByteString>> {
+        self.store.get(key)
+    }
+
+    pub fn get_with_meta(&self, key: &ByteStr) -> Result<Option<KeyValuePair>> {
+        self.store.get_with_meta(key)
+    }
+
+    pub fn get_at(&self, position: Position) -> Result<KeyValuePair> {
+        self.store.get_at(position)
+    }
+
+    pub fn get_reader(&self, key: &ByteStr) -> Result<Option<ValueReader<'_>>> {
+        self.store.get_reader(key)
+    }
+
+    pub fn get_as_of(&self, key: &ByteStr, seq: u64) -> Result<Option<ByteString>> {
+        self.store.get_as_of(key, seq)
+    }
+
+    pub fn history(&self, key: &ByteStr) -> Result<Vec<LogEntry>> {
+        self.store.history(key)
+    }
+
+    pub fn iter(&self) -> Entries<'_> {
+        self.store.iter()
+    }
+
+    pub fn iter_log(&self) -> LogEntries<'_> {
+        self.store.iter_log()
+    }
+
+    pub fn range<'k, R: RangeBounds<&'k ByteStr>>(&self, range: R) -> Entries<'_> {
+        self.store.range(range)
+    }
+
+    pub fn scan_prefix(&self, prefix: &ByteStr) -> Entries<'_> {
+        self.store.scan_prefix(prefix)
+    }
+
+    /// The index as it stood when the snapshot was taken
+    pub fn index(&self) -> &Index {
+        &self.store.index
+    }
+
+    /// Sequence number of the latest write the snapshot includes
+    pub fn last_seq(&self) -> u64 {
+        self.store.last_seq()
+    }
+
+    /// The segment files the snapshot reads from, with their lengths at the time
+    pub fn segments(&self) -> Vec<SegmentInfo> {
+        self.store.segments()
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use crate::{ActionKV, ByteStr, ByteString, KeyValuePair, Result, SharedActionKV, WriteBatch};
+
+/// Sequence number of every key read by a transaction as it was first read, or `None` for
+/// keys that were missing
+pub(crate) type ReadSet = BTreeMap<ByteString, Option<u64>>;
+
+/// Where a transaction reads from
+enum Source<'a> {
+    Store(&'a ActionKV),
+    Shared(&'a SharedActionKV),
+}
+
+/// Reads and buffered writes making up an optimistic transaction, as passed to the closure
+/// given to `ActionKV::transaction`
+///
+/// Writes are held back until the closure returns, and are visible to later reads in the
+/// same transaction. Each key read is remembered along with the sequence number of its
+/// value; reading it again returns the same value. At commit every key read is checked
+/// against the store, and the transaction fails with `ActionKvError::Conflict` if any of
+/// them has been written to since, leaving the caller to retry it.
+pub struct Transaction<'a> {
+    source: Source<'a>,
+    reads: ReadSet,
+    /// Values read, keyed like `reads`
+    values: BTreeMap<ByteString, Option<ByteString>>,
+    /// Pending writes in key order; a `None` value is a delete
+    writes: BTreeMap<ByteString, Option<ByteString>>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(store: &'a ActionKV) -> Self {
+        Transaction::with_source(Source::Store(store))
+    }
+
+    pub(crate) fn shared(store: &'a SharedActionKV) -> Self {
+        Transaction::with_source(Source::Shared(store))
+    }
+
+    fn with_source(source: Source<'a>) -> Self {
+        Self {
+            source,
+            reads: ReadSet::new(),
+            values: BTreeMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &ByteStr) -> Result<Option<ByteString>> {
+        if let Some(value) = self.writes.get(key) {
+            return Ok(value.clone());
+        }
+        if let Some(value) = self.values.get(key) {
+            return Ok(value.clone());
+        }
+
+        let kv = match self.source {
+            Source::Store(store) => store.get_with_meta(key)?,
+            Source::Shared(store) => store.get_with_meta(key)?,
+        };
+        let (seq, value) = match kv {
+            Some(KeyValuePair { seq, value, .. }) => (Some(seq), Some(value)),
+            None => (None, None),
+        };
+        self.reads.insert(key.to_vec(), seq);
+        self.values.insert(key.to_vec(), value.clone());
+
+        Ok(value)
+    }
+
+    pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    pub fn delete(&mut self, key: &ByteStr) {
+        self.writes.insert(key.to_vec(), None);
+    }
+
+    /// Splits the transaction into the keys it read and the batch of writes to commit
+    pub(crate) fn into_parts(self) -> (ReadSet, WriteBatch) {
+        let mut batch = WriteBatch::new();
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => batch.insert(&key, &value),
+                None => batch.delete(&key),
+            }
+        }
+
+        (self.reads, batch)
+    }
+}
@@ -0,0 +1,355 @@
+//! Rewriting segments written in older versions of the on-disk format
+//!
+//! The versions so far:
+//!
+//! - 0: no file header; records laid out as in version 1
+//! - 1: `<checksum u32><key_len u32><value_len u32>` record headers, with a value length of
+//!   `u32::MAX` marking a tombstone and a key length of `u32::MAX` a batch envelope. The
+//!   checksum is a CRC32 of the key and value only.
+//! - 2: the value length is widened to a `u64`, with `u64::MAX` marking a tombstone
+//! - 3: the checksum is widened to a `u64` and covers the lengths as well as the data, using
+//!   the `ChecksumKind` named in the file header
+//! - 4: a flags byte follows the checksum, saying how the value is compressed
+//! - 5: an expiry time follows the lengths, for values inserted with a TTL
+//! - 6: a sequence number and timestamp follow the expiry time; upgraded records get 0 for
+//!   both
+//! - 7: a CRC32 of the header fields follows them, so that a damaged length is caught before
+//!   it is used to read the record
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::checksum::Hasher;
+use crate::header::{header_len, Header};
+use crate::record::RecordHeader;
+use crate::{
+    hint, ActionKV, ActionKvError, ByteString, ChecksumKind, Result, BATCH, FORMAT_VERSION,
+    TOMBSTONE,
+};
+
+/// Key or value length marking a batch envelope or tombstone in version 1
+const V1_MARKER: u32 = u32::MAX;
+
+/// Rewrites the segment at `path`, written in format version `from` with its records
+/// checksummed by `old_checksum`, in the current format with its records checksummed by
+/// `checksum`
+///
+/// The new copy is synced and renamed over the original, so a crash leaves one or the other
+/// in place. A hint records offsets into the old layout, so it is removed first.
+///
+/// Only the newest segment, named by `tail`, can end in a torn record. A record that runs
+/// past the end of any other segment, or one followed by an intact record, must have had
+/// its length damaged, so the upgrade fails with `ActionKvError::Truncated` and the
+/// original is left as it was. Where a torn tail is carried over, the original is kept as
+/// `<name>.corrupt` first, as `load` may go on to cut the tail off.
+pub(crate) fn upgrade(
+    dir: &Path,
+    path: &Path,
+    from: u16,
+    old_checksum: ChecksumKind,
+    checksum: ChecksumKind,
+    tail: bool,
+) -> Result<()> {
+    if from >= FORMAT_VERSION {
+        return Err(ActionKvError::UnsupportedVersion { version: from });
+    }
+    let mut src = File::open(path)?;
+    src.seek(SeekFrom::Start(header_len(from)))?;
+
+    let tmp_path = path.with_extension("log.tmp");
+    let tmp = File::create(&tmp_path)?;
+    let converted = {
+        let mut dst = BufWriter::new(&tmp);
+        dst.write_all(&Header::new(checksum).encode())?;
+        let old = OldFormat {
+            version: from,
+            checksum: old_checksum,
+        };
+        convert_records(&mut BufReader::new(src), &mut dst, old, checksum, tail)
+            .and_then(|torn| Ok(dst.flush().map(|_| torn)?))
+    };
+    let torn = match converted {
+        Ok(torn) => torn,
+        Err(err) => {
+            drop(tmp);
+            std::fs::remove_file(&tmp_path)?;
+            return Err(err);
+        }
+    };
+    tmp.sync_all()?;
+    drop(tmp);
+
+    if torn.is_some() {
+        let backup = path.with_extension("log.corrupt");
+        std::fs::copy(path, &backup)?;
+        File::open(&backup)?.sync_all()?;
+    }
+    hint::remove(dir)?;
+    std::fs::rename(&tmp_path, path)?;
+    ActionKV::sync_dir(dir)?;
+
+    Ok(())
+}
+
+/// Returns the segment read from `src`, positioned just past its header, as it would be once
+/// upgraded from format `from`, without touching the original
+///
+/// Its records are checksummed with `checksum`, the kind the segment was already using.
+/// Damage is reported as by `upgrade`, with `tail` saying whether this is the newest segment.
+pub(crate) fn upgraded<R: Read + Seek>(
+    src: R,
+    from: u16,
+    checksum: ChecksumKind,
+    tail: bool,
+) -> Result<ByteString> {
+    let mut dst = Cursor::new(Header::new(checksum).encode());
+    dst.seek(SeekFrom::End(0))?;
+    let old = OldFormat {
+        version: from,
+        checksum,
+    };
+    convert_records(&mut BufReader::new(src), &mut dst, old, checksum, tail)?;
+
+    Ok(dst.into_inner())
+}
+
+/// Layout of the records being upgraded
+#[derive(Clone, Copy)]
+struct OldFormat {
+    version: u16,
+    checksum: ChecksumKind,
+}
+
+impl OldFormat {
+    fn record_header_len(self) -> u64 {
+        match self.version {
+            0 | 1 => 12,
+            2 => 16,
+            3 => 20,
+            4 => 21,
+            5 => 29,
+            _ => 45,
+        }
+    }
+
+    /// Splits a record header in this format into its checksum and the rest
+    fn decode(self, mut header: &[u8]) -> std::io::Result<(u64, RecordHeader)> {
+        let saved_checksum = match self.version {
+            0..=2 => header.read_u32::<LittleEndian>()? as u64,
+            _ => header.read_u64::<LittleEndian>()?,
+        };
+        let flags = match self.version {
+            0..=3 => 0,
+            _ => header.read_u8()?,
+        };
+        let key_len = header.read_u32::<LittleEndian>()?;
+        let value_len = match self.version {
+            0 | 1 => match header.read_u32::<LittleEndian>()? {
+                V1_MARKER if key_len != BATCH => TOMBSTONE,
+                value_len => value_len as u64,
+            },
+            _ => header.read_u64::<LittleEndian>()?,
+        };
+        let expires_at = match self.version {
+            0..=4 => 0,
+            _ => header.read_u64::<LittleEndian>()?,
+        };
+        let (seq, timestamp) = match self.version {
+            0..=5 => (0, 0),
+            _ => (
+                header.read_u64::<LittleEndian>()?,
+                header.read_u64::<LittleEndian>()?,
+            ),
+        };
+        let header = RecordHeader {
+            flags,
+            expires_at,
+            seq,
+            timestamp,
+            ..RecordHeader::new(key_len, value_len)
+        };
+
+        Ok((saved_checksum, header))
+    }
+
+    /// Starts the old checksum of a record with the given header, ready to be fed its data
+    fn hasher(self, header: &RecordHeader) -> Hasher {
+        if self.version <= 2 {
+            return ChecksumKind::Crc32.hasher();
+        }
+
+        let mut hasher = self.checksum.hasher();
+        if self.version >= 4 {
+            hasher.update(&[header.flags]);
+        }
+        hasher.update(&header.key_len.to_le_bytes());
+        hasher.update(&header.value_len.to_le_bytes());
+        if self.version >= 5 {
+            hasher.update(&header.expires_at.to_le_bytes());
+        }
+        if self.version >= 6 {
+            hasher.update(&header.seq.to_le_bytes());
+            hasher.update(&header.timestamp.to_le_bytes());
+        }
+        hasher
+    }
+
+    /// Whether a record that passes its checksum starts anywhere in `data`
+    ///
+    /// Records holding no data at all are passed over, as a run of zeroes would look like
+    /// one under the oldest formats, whose checksums only cover the data.
+    fn finds_record(self, data: &[u8]) -> bool {
+        let header_len = self.record_header_len() as usize;
+        (0..data.len().saturating_sub(header_len)).any(|start| {
+            let data = &data[start..];
+            let (saved_checksum, header) = match self.decode(&data[..header_len]) {
+                Ok(decoded) => decoded,
+                Err(_) => return false,
+            };
+            let data_len = header.data_len();
+            if data_len == 0 || data_len > (data.len() - header_len) as u64 {
+                return false;
+            }
+
+            let mut hasher = self.hasher(&header);
+            hasher.update(&data[header_len..header_len + data_len as usize]);
+            hasher.finish() == saved_checksum
+        })
+    }
+}
+
+/// Copies records written in the `old` format from `src` to `dst` in the current layout
+///
+/// Every record is given a new checksum, and one that failed its old checksum is given a new
+/// one that is certain to fail too. A batch's payload is itself made up of records, which are
+/// converted if it was intact and copied untouched otherwise.
+///
+/// A record that runs past the end of `src` is only taken for a torn tail if `tail` is set
+/// and no intact record can be found after its header; it is copied as far as it goes, apart
+/// from a partial header or batch, which `load` would discard anyway, and its offset is
+/// returned. Otherwise it fails the conversion with `ActionKvError::Truncated`.
+fn convert_records<R: Read + Seek, W: Write + Seek>(
+    src: &mut R,
+    dst: &mut W,
+    old: OldFormat,
+    checksum: ChecksumKind,
+    tail: bool,
+) -> Result<Option<u64>> {
+    let old_header_len = old.record_header_len();
+    loop {
+        let pos = src.stream_position()?;
+        let mut header = ByteString::with_capacity(old_header_len as usize);
+        src.by_ref().take(old_header_len).read_to_end(&mut header)?;
+        if header.is_empty() {
+            return Ok(None);
+        }
+        if header.len() as u64 != old_header_len {
+            return torn_tail(src, old, pos, tail);
+        }
+
+        let (saved_checksum, header) = old.decode(&header)?;
+        let old_hasher = old.hasher(&header);
+
+        if header.is_batch() {
+            let mut payload = ByteString::new();
+            src.by_ref()
+                .take(header.value_len)
+                .read_to_end(&mut payload)?;
+            if payload.len() as u64 != header.value_len {
+                return torn_tail(src, old, pos, tail);
+            }
+
+            let mut hasher = old_hasher.clone();
+            hasher.update(&payload);
+            let (payload, old) = if hasher.finish() == saved_checksum {
+                let mut converted = Cursor::new(ByteString::new());
+                let mut records = Cursor::new(&payload[..]);
+                convert_records(&mut records, &mut converted, old, checksum, false)?;
+                (converted.into_inner(), None)
+            } else {
+                (payload, Some((old_hasher, saved_checksum)))
+            };
+            let header = RecordHeader {
+                timestamp: header.timestamp,
+                ..RecordHeader::new(BATCH, payload.len() as u64)
+            };
+            copy_record(&mut &payload[..], dst, header, old, checksum)?;
+            continue;
+        }
+
+        let checked = Some((old_hasher, saved_checksum));
+        if !copy_record(src, dst, header, checked, checksum)? {
+            return torn_tail(src, old, pos, tail);
+        }
+    }
+}
+
+/// Decides what to make of the record at `pos` running past the end of `src`: a torn tail,
+/// whose offset is returned, if `tail` is set and nothing intact follows its header, and
+/// damage otherwise
+fn torn_tail<R: Read + Seek>(
+    src: &mut R,
+    old: OldFormat,
+    pos: u64,
+    tail: bool,
+) -> Result<Option<u64>> {
+    if tail {
+        src.seek(SeekFrom::Start(pos + old.record_header_len()))?;
+        let mut rest = ByteString::new();
+        src.read_to_end(&mut rest)?;
+        if !old.finds_record(&rest) {
+            return Ok(Some(pos));
+        }
+    }
+
+    Err(ActionKvError::Truncated { offset: pos })
+}
+/// Writes a record with the given header to `dst`, copying its data from `src`
+///
+/// The checksum is filled in once the data has been copied, and deliberately spoiled if
+/// the data doesn't match `old`, a checksum of it in the making and the value it should
+/// come to. Returns `false` if `src` runs out first.
+pub(crate) fn copy_record<R: Read, W: Write + Seek>(
+    src: &mut R,
+    dst: &mut W,
+    header: RecordHeader,
+    old: Option<(Hasher, u64)>,
+    checksum: ChecksumKind,
+) -> Result<bool> {
+    let start = dst.stream_position()?;
+    header.write(dst, 0)?;
+
+    let (mut old, old_checksum) = match old {
+        Some((hasher, old_checksum)) => (Some(hasher), Some(old_checksum)),
+        None => (None, None),
+    };
+    let mut new = header.hasher(checksum);
+    let mut buf = [0; 8 * 1024];
+    let mut remaining = header.data_len();
+    while remaining > 0 {
+        let wanted = remaining.min(buf.len() as u64) as usize;
+        let n = src.read(&mut buf[..wanted])?;
+        if n == 0 {
+            return Ok(false);
+        }
+        if let Some(old) = &mut old {
+            old.update(&buf[..n]);
+        }
+        new.update(&buf[..n]);
+        dst.write_all(&buf[..n])?;
+        remaining -= n as u64;
+    }
+
+    let mut new_checksum = new.finish();
+    if old.is_some_and(|old| Some(old.finish()) != old_checksum) {
+        new_checksum = !new_checksum;
+    }
+    dst.seek(SeekFrom::Start(start))?;
+    dst.write_u64::<LittleEndian>(new_checksum)?;
+    dst.seek(SeekFrom::End(0))?;
+
+    Ok(true)
+}
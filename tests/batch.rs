@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use libactionkv::{ActionKV, Compression, Options, WriteBatch};
+
+fn open(dir: &Path, options: Options) -> ActionKV {
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    store
+}
+
+#[test]
+fn batch_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        compression: Compression::Lz4,
+        ..Options::default()
+    };
+    let mut store = open(dir.path(), options.clone());
+    store.insert(b"deleted", b"value").unwrap();
+    store.insert(b"kept", b"value").unwrap();
+    let before = store.last_seq();
+
+    let mut batch = WriteBatch::new();
+    batch.insert(b"a", b"1");
+    batch.insert(b"b", &b"long and repetitive ".repeat(20));
+    batch.delete(b"deleted");
+    batch.insert(b"a", b"2");
+    batch.delete(b"missing");
+    assert_eq!(batch.len(), 5);
+    store.write_batch(batch).unwrap();
+    assert_eq!(store.last_seq(), before + 5);
+
+    // An empty batch writes nothing
+    let len = store.segments()[0].len;
+    store.write_batch(WriteBatch::new()).unwrap();
+    assert_eq!(store.segments()[0].len, len);
+    assert_eq!(store.last_seq(), before + 5);
+    drop(store);
+
+    let mut store = open(dir.path(), options);
+    assert_eq!(store.last_seq(), before + 5);
+    // Later operations on the same key win
+    let a = store.get_with_meta(b"a").unwrap().unwrap();
+    assert_eq!(a.value, b"2");
+    assert_eq!(a.seq, before + 4);
+    assert_eq!(
+        store.get(b"b").unwrap(),
+        Some(b"long and repetitive ".repeat(20))
+    );
+    assert_eq!(store.get(b"deleted").unwrap(), None);
+    assert_eq!(store.get(b"kept").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(store.get(b"missing").unwrap(), None);
+
+    // Each operation shows up in the log in order, with its own sequence number
+    let seqs: Vec<_> = store
+        .iter_log()
+        .map(|entry| entry.unwrap().seq)
+        .filter(|seq| *seq > before)
+        .collect();
+    assert_eq!(seqs, (before + 1..=before + 5).collect::<Vec<_>>());
+
+    store.compact().unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(store.get(b"deleted").unwrap(), None);
+    assert_eq!(store.iter().count(), 3);
+}
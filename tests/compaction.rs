@@ -0,0 +1,137 @@
+use libactionkv::{ActionKV, ActionKvError, EncryptionKey, Options};
+
+#[test]
+fn compaction_needs_a_loaded_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    store.insert(b"x", b"1").unwrap();
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    assert!(matches!(store.compact(), Err(ActionKvError::NotLoaded)));
+    assert!(matches!(store.merge(1..=1), Err(ActionKvError::NotLoaded)));
+    assert!(matches!(store.write_hint(), Err(ActionKvError::NotLoaded)));
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    store.compact().unwrap();
+    assert_eq!(store.get(b"x").unwrap(), Some(b"1".to_vec()));
+}
+
+#[test]
+fn merge_finds_tombstones_without_decoding_values() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        max_segment_size: 200,
+        encryption_key: Some(EncryptionKey::new([1; 32])),
+        ..Options::default()
+    };
+    let mut store = ActionKV::open_with(dir.path(), options.clone()).unwrap();
+    store.load().unwrap();
+    for i in 0..10 {
+        let key = format!("key{}", i);
+        store.insert(key.as_bytes(), b"value").unwrap();
+        store.delete(key.as_bytes()).unwrap();
+    }
+    drop(store);
+
+    // Without the key every value would fail to decrypt, were it read back
+    let without_key = Options {
+        encryption_key: None,
+        ..options.clone()
+    };
+    let mut store = ActionKV::open_with(dir.path(), without_key).unwrap();
+    store.load().unwrap();
+    let ids: Vec<u32> = store.segments().iter().map(|segment| segment.id).collect();
+    assert!(ids.len() > 3);
+    store.merge(ids[1]..=ids[ids.len() - 2]).unwrap();
+    drop(store);
+
+    let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+    store.load().unwrap();
+    for i in 0..10 {
+        let key = format!("key{}", i);
+        assert_eq!(store.get(key.as_bytes()).unwrap(), None);
+    }
+}
+
+#[test]
+fn merge_keeps_older_versions_hidden() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        max_segment_size: 100,
+        ..Options::default()
+    };
+    let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+    store.load().unwrap();
+    store.insert(b"deleted", b"1").unwrap();
+    store.insert(b"overwritten", b"1").unwrap();
+    let before = store.last_seq();
+    store.delete(b"deleted").unwrap();
+    store.insert(b"overwritten", b"2").unwrap();
+    let between = store.last_seq();
+    store.insert(b"deleted", b"3").unwrap();
+    store.insert(b"overwritten", b"3").unwrap();
+    store.insert(b"filler", b"").unwrap();
+
+    let ids: Vec<u32> = store.segments().iter().map(|segment| segment.id).collect();
+    assert!(ids.len() >= 4);
+    store.merge(ids[1]..=ids[ids.len() - 2]).unwrap();
+    assert!(store.segments().len() < ids.len());
+
+    assert_eq!(
+        store.get_as_of(b"deleted", before).unwrap(),
+        Some(b"1".to_vec())
+    );
+    assert_eq!(store.get_as_of(b"deleted", between).unwrap(), None);
+    assert_eq!(store.get_as_of(b"overwritten", between).unwrap(), None);
+    assert_eq!(store.get(b"deleted").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(store.get(b"overwritten").unwrap(), Some(b"3".to_vec()));
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    assert_eq!(store.get(b"deleted").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(store.get(b"overwritten").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn writes_need_a_loaded_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    store.insert(b"x", b"1").unwrap();
+    let last_seq = store.last_seq();
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    assert!(matches!(
+        store.insert(b"x", b"2"),
+        Err(ActionKvError::NotLoaded)
+    ));
+    assert!(matches!(store.delete(b"x"), Err(ActionKvError::NotLoaded)));
+    store.load().unwrap();
+    store.insert(b"x", b"2").unwrap();
+    assert_eq!(store.last_seq(), last_seq + 1);
+}
+
+#[test]
+fn compaction_keeps_the_latest_sequence_number() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    store.insert(b"a", b"1").unwrap();
+    store.insert(b"b", b"2").unwrap();
+    store.delete(b"b").unwrap();
+    assert_eq!(store.last_seq(), 3);
+    store.compact().unwrap();
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    assert_eq!(store.last_seq(), 3);
+    store.insert(b"c", b"3").unwrap();
+    assert_eq!(store.get_with_meta(b"c").unwrap().unwrap().seq, 4);
+}
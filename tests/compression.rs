@@ -0,0 +1,102 @@
+use std::io::Read;
+use std::path::Path;
+
+use libactionkv::{ActionKV, Compression, Options};
+
+const COMPRESSIONS: [Compression; 4] = [
+    Compression::None,
+    Compression::Lz4,
+    Compression::Zstd(0),
+    Compression::Zstd(19),
+];
+
+fn open(dir: &Path, options: Options) -> ActionKV {
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    store
+}
+
+fn compressible() -> Vec<u8> {
+    b"the same few words over and over ".repeat(100)
+}
+
+#[test]
+fn compressed_values_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path(), Options::default());
+    let value = compressible();
+    for (i, compression) in COMPRESSIONS.into_iter().enumerate() {
+        let key = format!("key{}", i);
+        store
+            .insert_compressed(key.as_bytes(), &value, compression)
+            .unwrap();
+    }
+    // Doesn't get any smaller, so is stored as it is
+    store
+        .insert_compressed(b"short", b"abc", Compression::Zstd(0))
+        .unwrap();
+    drop(store);
+
+    let store = open(dir.path(), Options::default());
+    for i in 0..COMPRESSIONS.len() {
+        let key = format!("key{}", i);
+        assert_eq!(store.get(key.as_bytes()).unwrap(), Some(value.clone()));
+
+        let mut read = Vec::new();
+        let mut reader = store.get_reader(key.as_bytes()).unwrap().unwrap();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, value);
+    }
+    assert_eq!(store.get(b"short").unwrap(), Some(b"abc".to_vec()));
+}
+
+#[test]
+fn compression_shrinks_the_log() {
+    let value = compressible();
+    let mut sizes = Vec::new();
+    for compression in COMPRESSIONS {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            compression,
+            ..Options::default()
+        };
+        let mut store = open(dir.path(), options);
+        store.insert(b"key", &value).unwrap();
+        assert_eq!(store.get(b"key").unwrap(), Some(value.clone()));
+        sizes.push(store.segments()[0].len);
+    }
+
+    assert!(sizes[0] > value.len() as u64);
+    for size in &sizes[1..] {
+        assert!(*size < sizes[0] / 4, "{:?}", sizes);
+    }
+}
+
+#[test]
+fn mixed_compression_survives_compaction() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        compression: Compression::Lz4,
+        max_segment_size: 500,
+        ..Options::default()
+    };
+    let mut store = open(dir.path(), options.clone());
+    let value = compressible();
+    for i in 0..20 {
+        let key = format!("key{}", i % 8);
+        let compression = COMPRESSIONS[i % COMPRESSIONS.len()];
+        store
+            .insert_compressed(key.as_bytes(), &value, compression)
+            .unwrap();
+    }
+    store.insert(b"default", &value).unwrap();
+    store.compact().unwrap();
+    drop(store);
+
+    let store = open(dir.path(), options);
+    for i in 0..8 {
+        let key = format!("key{}", i);
+        assert_eq!(store.get(key.as_bytes()).unwrap(), Some(value.clone()));
+    }
+    assert_eq!(store.get(b"default").unwrap(), Some(value));
+}
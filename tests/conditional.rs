@@ -0,0 +1,69 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use libactionkv::{ActionKV, ActionKvError, Options, Result};
+
+fn open(dir: &Path) -> ActionKV {
+    let mut store = ActionKV::open_with(dir, Options::default()).unwrap();
+    store.load().unwrap();
+    store
+}
+
+fn is_conflict(result: Result<()>, key: &[u8]) -> bool {
+    matches!(result, Err(ActionKvError::Conflict { key: k }) if k == key)
+}
+
+#[test]
+fn compare_and_swap_only_writes_on_a_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path());
+    store.insert(b"k", b"1").unwrap();
+    let len = store.segments()[0].len;
+    let last_seq = store.last_seq();
+
+    assert!(is_conflict(
+        store.compare_and_swap(b"k", Some(b"2"), b"3"),
+        b"k"
+    ));
+    assert!(is_conflict(store.compare_and_swap(b"k", None, b"3"), b"k"));
+    assert!(is_conflict(
+        store.compare_and_swap(b"missing", Some(b"1"), b"3"),
+        b"missing"
+    ));
+    assert_eq!(store.get(b"k").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(store.get(b"missing").unwrap(), None);
+    assert_eq!(store.segments()[0].len, len);
+    assert_eq!(store.last_seq(), last_seq);
+
+    store.compare_and_swap(b"k", Some(b"1"), b"2").unwrap();
+    store.compare_and_swap(b"missing", None, b"new").unwrap();
+    drop(store);
+
+    let store = open(dir.path());
+    assert_eq!(store.get(b"k").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(store.get(b"missing").unwrap(), Some(b"new".to_vec()));
+}
+
+#[test]
+fn insert_if_absent_only_writes_missing_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path());
+
+    store.insert_if_absent(b"k", b"first").unwrap();
+    assert!(is_conflict(store.insert_if_absent(b"k", b"second"), b"k"));
+    assert_eq!(store.get(b"k").unwrap(), Some(b"first".to_vec()));
+
+    store.delete(b"k").unwrap();
+    store.insert_if_absent(b"k", b"third").unwrap();
+    assert_eq!(store.get(b"k").unwrap(), Some(b"third".to_vec()));
+
+    // An expired value counts as missing
+    store
+        .insert_with_ttl(b"ttl", b"old", Duration::from_millis(50))
+        .unwrap();
+    assert!(is_conflict(store.insert_if_absent(b"ttl", b"new"), b"ttl"));
+    thread::sleep(Duration::from_millis(100));
+    store.insert_if_absent(b"ttl", b"new").unwrap();
+    assert_eq!(store.get(b"ttl").unwrap(), Some(b"new".to_vec()));
+}
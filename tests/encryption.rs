@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+
+use crc::{Crc, CRC_32_CKSUM};
+use libactionkv::{ActionKV, ActionKvError, EncryptionKey, Options};
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
+
+/// Size of a segment's file header, after which its first record starts
+const FILE_HEADER_LEN: usize = 23;
+
+const RECORD_HEADER_LEN: usize = 49;
+
+/// Offsets of fields within a record header
+const FLAGS_OFFSET: usize = 8;
+const EXPIRES_AT_OFFSET: usize = 8 + 1 + 4 + 8;
+const SEQ_OFFSET: usize = EXPIRES_AT_OFFSET + 8;
+const TIMESTAMP_OFFSET: usize = SEQ_OFFSET + 8;
+
+fn encrypted(key: u8) -> Options {
+    Options {
+        encryption_key: Some(EncryptionKey::new([key; 32])),
+        ..Options::default()
+    }
+}
+
+fn write(dir: &Path, options: Options, key: &[u8], value: &[u8]) -> PathBuf {
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    store.insert(key, value).unwrap();
+    store.segments()[0].path.clone()
+}
+
+fn get(dir: &Path, options: Options, key: &[u8]) -> libactionkv::Result<Option<Vec<u8>>> {
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    store.get(key)
+}
+
+/// Changes the first record in the segment at `path` with `tamper`, then works its checksums
+/// out again, so that only the encryption can tell
+fn tamper_with_first_record(path: &Path, tamper: impl FnOnce(&mut [u8])) {
+    let mut data = std::fs::read(path).unwrap();
+    let record = &mut data[FILE_HEADER_LEN..];
+    tamper(record);
+
+    let fields = 8..RECORD_HEADER_LEN - 4;
+    let header_checksum = CRC32.checksum(&record[fields.clone()]);
+    record[fields.end..RECORD_HEADER_LEN].copy_from_slice(&header_checksum.to_le_bytes());
+    let mut digest = CRC32.digest();
+    digest.update(&record[fields]);
+    digest.update(&record[RECORD_HEADER_LEN..]);
+    let checksum = digest.finalize() as u64;
+    record[..8].copy_from_slice(&checksum.to_le_bytes());
+
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn values_only_decrypt_with_their_key() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), encrypted(1), b"k", b"secret");
+
+    assert_eq!(
+        get(dir.path(), encrypted(1), b"k").unwrap(),
+        Some(b"secret".to_vec())
+    );
+    assert!(matches!(
+        get(dir.path(), encrypted(2), b"k"),
+        Err(ActionKvError::Decryption { .. })
+    ));
+    assert!(matches!(
+        get(dir.path(), Options::default(), b"k"),
+        Err(ActionKvError::Decryption { .. })
+    ));
+}
+
+#[test]
+fn plaintext_is_refused_when_encryption_is_required() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), Options::default(), b"k", b"plain");
+
+    assert_eq!(
+        get(dir.path(), encrypted(1), b"k").unwrap(),
+        Some(b"plain".to_vec())
+    );
+    let options = Options {
+        require_encryption: true,
+        ..encrypted(1)
+    };
+    assert!(matches!(
+        get(dir.path(), options, b"k"),
+        Err(ActionKvError::Decryption { .. })
+    ));
+}
+
+#[test]
+fn flags_are_authenticated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(dir.path(), encrypted(1), b"k", b"secret");
+    tamper_with_first_record(&path, |record| record[FLAGS_OFFSET] ^= 0b01);
+
+    assert!(matches!(
+        get(dir.path(), encrypted(1), b"k"),
+        Err(ActionKvError::Decryption { .. })
+    ));
+}
+
+#[test]
+fn sequence_numbers_are_authenticated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(dir.path(), encrypted(1), b"k", b"secret");
+    tamper_with_first_record(&path, |record| record[SEQ_OFFSET] ^= 0x40);
+
+    assert!(matches!(
+        get(dir.path(), encrypted(1), b"k"),
+        Err(ActionKvError::Decryption { .. })
+    ));
+}
+
+#[test]
+fn expiry_times_are_authenticated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(dir.path(), encrypted(1), b"k", b"secret");
+    // Far enough in the future that the value doesn't simply count as expired
+    tamper_with_first_record(&path, |record| record[EXPIRES_AT_OFFSET + 6] ^= 0x01);
+
+    assert!(matches!(
+        get(dir.path(), encrypted(1), b"k"),
+        Err(ActionKvError::Decryption { .. })
+    ));
+}
+
+#[test]
+fn timestamps_are_authenticated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write(dir.path(), encrypted(1), b"k", b"secret");
+    tamper_with_first_record(&path, |record| record[TIMESTAMP_OFFSET] ^= 0x40);
+
+    assert!(matches!(
+        get(dir.path(), encrypted(1), b"k"),
+        Err(ActionKvError::Decryption { .. })
+    ));
+}
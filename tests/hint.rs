@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use libactionkv::{ActionKV, LoadReport, Options};
+
+fn with_hint(hint_file: bool) -> Options {
+    Options {
+        hint_file,
+        max_segment_size: 300,
+        ..Options::default()
+    }
+}
+
+fn load(dir: &Path, options: Options) -> (ActionKV, LoadReport) {
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    let report = store.load().unwrap();
+    (store, report)
+}
+
+/// Writes `key{i}` for `i` in `range`, overwriting every other one
+fn fill(store: &mut ActionKV, range: std::ops::Range<u32>) {
+    for i in range {
+        let key = format!("key{}", i);
+        store.insert(key.as_bytes(), b"first").unwrap();
+        if i % 2 == 0 {
+            store.insert(key.as_bytes(), b"second").unwrap();
+        }
+    }
+}
+
+fn check_values(store: &ActionKV, n: u32) {
+    for i in 0..n {
+        let key = format!("key{}", i);
+        let expected: &[u8] = if i % 2 == 0 { b"second" } else { b"first" };
+        assert_eq!(
+            store.get(key.as_bytes()).unwrap().as_deref(),
+            Some(expected)
+        );
+    }
+    assert_eq!(store.iter().count(), n as usize);
+}
+
+#[test]
+fn hint_stands_in_for_the_records_it_covers() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut store, _) = load(dir.path(), with_hint(true));
+    fill(&mut store, 0..10);
+    let last_seq = store.last_seq();
+    assert!(store.segments().len() > 1);
+    drop(store);
+    assert!(dir.path().join("index.hint").exists());
+
+    let (store, report) = load(dir.path(), with_hint(true));
+    assert!(report.used_hint);
+    assert_eq!(report.records, 0);
+    assert_eq!(store.last_seq(), last_seq);
+    check_values(&store, 10);
+    drop(store);
+
+    // Records appended without updating the hint are scanned on top of it
+    let (mut store, _) = load(dir.path(), with_hint(false));
+    fill(&mut store, 10..14);
+    store.delete(b"key0").unwrap();
+    let last_seq = store.last_seq();
+    drop(store);
+
+    let (store, report) = load(dir.path(), with_hint(true));
+    assert!(report.used_hint);
+    assert_eq!(report.records, 4 + 2 + 1);
+    assert_eq!(store.last_seq(), last_seq);
+    assert_eq!(store.get(b"key0").unwrap(), None);
+    assert_eq!(store.get(b"key13").unwrap(), Some(b"first".to_vec()));
+}
+
+#[test]
+fn stale_hint_falls_back_to_a_full_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut store, _) = load(dir.path(), with_hint(true));
+    fill(&mut store, 0..10);
+    drop(store);
+
+    // Compacting without the hint replaces the segments it was taken from
+    let (mut store, _) = load(dir.path(), with_hint(false));
+    store.compact().unwrap();
+    drop(store);
+
+    let (store, report) = load(dir.path(), with_hint(true));
+    assert!(!report.used_hint);
+    assert_eq!(report.records, 10);
+    check_values(&store, 10);
+}
+
+#[test]
+fn damaged_hint_falls_back_to_a_full_scan() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut store, _) = load(dir.path(), with_hint(true));
+    fill(&mut store, 0..10);
+    drop(store);
+
+    let path = dir.path().join("index.hint");
+    let mut data = std::fs::read(&path).unwrap();
+    let middle = data.len() / 2;
+    data[middle] ^= 0xff;
+    std::fs::write(&path, &data).unwrap();
+
+    let (store, report) = load(dir.path(), with_hint(true));
+    assert!(!report.used_hint);
+    assert_eq!(report.records, 15);
+    check_values(&store, 10);
+}
@@ -0,0 +1,42 @@
+use libactionkv::{ActionKV, ActionKvError, Options};
+
+fn read_only() -> Options {
+    Options {
+        read_only: true,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn readers_share_and_writers_exclude() {
+    let dir = tempfile::tempdir().unwrap();
+    let writer = ActionKV::open(dir.path()).unwrap();
+    assert!(matches!(
+        ActionKV::open_with(dir.path(), read_only()),
+        Err(ActionKvError::Locked)
+    ));
+    drop(writer);
+
+    let reader = ActionKV::open_with(dir.path(), read_only()).unwrap();
+    let other = ActionKV::open_with(dir.path(), read_only()).unwrap();
+    assert!(matches!(
+        ActionKV::open(dir.path()),
+        Err(ActionKvError::Locked)
+    ));
+    drop((reader, other));
+    ActionKV::open(dir.path()).unwrap();
+}
+
+#[test]
+fn reader_recreates_a_missing_lock_file() {
+    let dir = tempfile::tempdir().unwrap();
+    drop(ActionKV::open(dir.path()).unwrap());
+    std::fs::remove_file(dir.path().join("LOCK")).unwrap();
+
+    let reader = ActionKV::open_with(dir.path(), read_only()).unwrap();
+    assert!(matches!(
+        ActionKV::open(dir.path()),
+        Err(ActionKvError::Locked)
+    ));
+    drop(reader);
+}
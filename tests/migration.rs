@@ -0,0 +1,55 @@
+use std::io::Write;
+use std::path::Path;
+
+use crc::{Crc, CRC_32_CKSUM};
+use libactionkv::{ActionKV, ActionKvError, Options};
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_CKSUM);
+
+/// Writes a store as a single file in the original format:
+/// `<checksum u32><key_len u32><value_len u32><key><value>`
+fn write_single_file(path: &Path, pairs: &[(&[u8], &[u8])]) {
+    let mut f = std::fs::File::create(path).unwrap();
+    for (key, value) in pairs {
+        let data = [*key, *value].concat();
+        f.write_all(&CRC32.checksum(&data).to_le_bytes()).unwrap();
+        f.write_all(&(key.len() as u32).to_le_bytes()).unwrap();
+        f.write_all(&(value.len() as u32).to_le_bytes()).unwrap();
+        f.write_all(&data).unwrap();
+    }
+}
+
+#[test]
+fn single_file_store_becomes_a_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store.db");
+    write_single_file(&path, &[(b"a", b"1"), (b"b", b"2"), (b"a", b"3")]);
+
+    let options = Options {
+        read_only: true,
+        ..Options::default()
+    };
+    assert!(matches!(
+        ActionKV::open_with(&path, options),
+        Err(ActionKvError::ReadOnly)
+    ));
+    assert!(path.is_file());
+
+    let mut store = ActionKV::open(&path).unwrap();
+    store.load().unwrap();
+    assert!(path.is_dir());
+    assert_eq!(store.get(b"a").unwrap(), Some(b"3".to_vec()));
+    assert_eq!(store.get(b"b").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn interrupted_migration_is_finished() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store.db");
+    write_single_file(&dir.path().join("store.db.migrating"), &[(b"a", b"1")]);
+
+    let mut store = ActionKV::open(&path).unwrap();
+    store.load().unwrap();
+    assert_eq!(store.get(b"a").unwrap(), Some(b"1".to_vec()));
+    assert!(!dir.path().join("store.db.migrating").exists());
+}
@@ -0,0 +1,118 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use libactionkv::{ActionKV, Entries, IndexKind, Options};
+
+fn open(dir: &Path, index: IndexKind) -> ActionKV {
+    let options = Options {
+        index,
+        ..Options::default()
+    };
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    store
+}
+
+/// Writes keys out of order, overwriting one and deleting another, so that neither the log
+/// nor the index happens to be in key order already
+fn fill(store: &mut ActionKV) {
+    for key in [
+        "fig", "apple", "date", "banana", "cherry", "elder", "apricot",
+    ] {
+        store
+            .insert(key.as_bytes(), key.to_uppercase().as_bytes())
+            .unwrap();
+    }
+    store.insert(b"banana", b"BANANA2").unwrap();
+    store.delete(b"elder").unwrap();
+}
+
+fn keys(entries: Entries) -> Vec<String> {
+    entries
+        .map(|kv| String::from_utf8(kv.unwrap().key).unwrap())
+        .collect()
+}
+
+#[test]
+fn ranges_come_back_in_key_order() {
+    for kind in [IndexKind::Hashed, IndexKind::Ordered] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = open(dir.path(), kind);
+        fill(&mut store);
+
+        assert_eq!(
+            keys(store.range(&b"b"[..]..&b"e"[..])),
+            ["banana", "cherry", "date"],
+            "{:?}",
+            kind
+        );
+        assert_eq!(
+            keys(store.range(&b"cherry"[..]..)),
+            ["cherry", "date", "fig"],
+            "{:?}",
+            kind
+        );
+        assert_eq!(
+            keys(store.range(..=&b"apricot"[..])),
+            ["apple", "apricot"],
+            "{:?}",
+            kind
+        );
+        let bounds = (
+            Bound::Excluded(&b"apple"[..]),
+            Bound::Excluded(&b"cherry"[..]),
+        );
+        assert_eq!(
+            keys(store.range(bounds)),
+            ["apricot", "banana"],
+            "{:?}",
+            kind
+        );
+        assert!(keys(store.range(&b"x"[..]..)).is_empty(), "{:?}", kind);
+
+        let values: Vec<_> = store
+            .range(&b"banana"[..]..=&b"banana"[..])
+            .map(|kv| kv.unwrap().value)
+            .collect();
+        assert_eq!(values, [b"BANANA2".to_vec()], "{:?}", kind);
+    }
+}
+
+#[test]
+fn prefixes_come_back_in_key_order() {
+    for kind in [IndexKind::Hashed, IndexKind::Ordered] {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = open(dir.path(), kind);
+        fill(&mut store);
+
+        assert_eq!(
+            keys(store.scan_prefix(b"ap")),
+            ["apple", "apricot"],
+            "{:?}",
+            kind
+        );
+        assert_eq!(
+            keys(store.scan_prefix(b"e")),
+            Vec::<String>::new(),
+            "{:?}",
+            kind
+        );
+        assert_eq!(keys(store.scan_prefix(b"")).len(), 6, "{:?}", kind);
+        drop(store);
+
+        // The index rebuilt by `load` answers the same way
+        let store = open(dir.path(), kind);
+        assert_eq!(
+            keys(store.scan_prefix(b"ap")),
+            ["apple", "apricot"],
+            "{:?}",
+            kind
+        );
+        assert_eq!(
+            keys(store.scan_prefix(b"")),
+            ["apple", "apricot", "banana", "cherry", "date", "fig"],
+            "{:?}",
+            kind
+        );
+    }
+}
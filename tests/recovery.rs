@@ -0,0 +1,338 @@
+use std::path::Path;
+
+use libactionkv::{ActionKV, ActionKvError, ChecksumKind, Options, RecoveryPolicy, WriteBatch};
+
+/// Size of a segment's file header, after which its first record starts
+const FILE_HEADER_LEN: usize = 23;
+
+/// Offset of `value_len` within a record header
+const VALUE_LEN_OFFSET: usize = 8 + 1 + 4;
+
+fn fill(dir: &Path, options: Options, n: u32) {
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    for i in 0..n {
+        let key = format!("key{}", i);
+        let value = format!("value{}", i);
+        store.insert(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+    store.sync().unwrap();
+}
+
+fn segment_path(dir: &Path, n: usize) -> std::path::PathBuf {
+    let store = ActionKV::open(dir).unwrap();
+    store.segments()[n].path.clone()
+}
+
+#[test]
+fn torn_tail_is_cut_off() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), Options::default(), 10);
+
+    let path = segment_path(dir.path(), 0);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let f = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    f.set_len(len - 3).unwrap();
+    drop(f);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    let report = store.load().unwrap();
+    assert!(report.truncated);
+    assert_eq!(report.records, 9);
+    let last_record = "key9".len() as u64 + "value9".len() as u64 + 49;
+    assert_eq!(report.discarded_bytes, last_record - 3);
+    assert_eq!(store.get(b"key8").unwrap(), Some(b"value8".to_vec()));
+    assert_eq!(store.get(b"key9").unwrap(), None);
+
+    store.insert(b"key9", b"again").unwrap();
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    let report = store.load().unwrap();
+    assert!(!report.truncated);
+    assert_eq!(store.get(b"key9").unwrap(), Some(b"again".to_vec()));
+}
+
+#[test]
+fn torn_tail_is_cut_off_when_the_checksum_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), Options::default(), 10);
+
+    let path = segment_path(dir.path(), 0);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let f = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    f.set_len(len - 3).unwrap();
+    drop(f);
+
+    let options = Options {
+        checksum: ChecksumKind::XxHash64,
+        ..Options::default()
+    };
+    let mut store = ActionKV::open_with(dir.path(), options.clone()).unwrap();
+    let report = store.load().unwrap();
+    assert!(report.truncated);
+    assert_eq!(report.records, 9);
+    assert_eq!(store.segments().len(), 1);
+
+    store.insert(b"key9", b"again").unwrap();
+    let segments = store.segments();
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].path, path);
+    drop(store);
+
+    let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+    let report = store.load().unwrap();
+    assert!(!report.truncated);
+    assert_eq!(report.records, 10);
+    assert_eq!(store.get(b"key8").unwrap(), Some(b"value8".to_vec()));
+    assert_eq!(store.get(b"key9").unwrap(), Some(b"again".to_vec()));
+}
+
+#[test]
+fn unfinished_streamed_value_is_a_torn_write() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), Options::default(), 2);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    let value = vec![7; 1000];
+    store.insert_from_reader(b"big", &value[..], 1000).unwrap();
+    drop(store);
+
+    // A crash before the checksum is filled in leaves it zeroed
+    let path = segment_path(dir.path(), 0);
+    let mut data = std::fs::read(&path).unwrap();
+    let start = data.len() - (49 + 3 + 1000);
+    data[start..start + 8].fill(0);
+    std::fs::write(&path, &data).unwrap();
+
+    let report = ActionKV::check(dir.path()).unwrap();
+    assert!(report.segments[0].corrupt.is_empty());
+    assert_eq!(report.segments[0].torn_tail.unwrap().offset, start as u64);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    let report = store.load().unwrap();
+    assert!(report.truncated);
+    assert_eq!(report.records, 2);
+    assert_eq!(store.get(b"big").unwrap(), None);
+    assert_eq!(store.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+    drop(store);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), start as u64);
+}
+
+#[test]
+fn zeroed_checksum_before_the_end_is_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), Options::default(), 2);
+
+    let path = segment_path(dir.path(), 0);
+    let mut data = std::fs::read(&path).unwrap();
+    data[FILE_HEADER_LEN..FILE_HEADER_LEN + 8].fill(0);
+    std::fs::write(&path, &data).unwrap();
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    match store.load() {
+        Err(ActionKvError::Corruption { offset, .. }) => {
+            assert_eq!(offset, FILE_HEADER_LEN as u64)
+        }
+        other => panic!("expected corruption, got {:?}", other),
+    }
+}
+
+#[test]
+fn damaged_length_is_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), Options::default(), 10);
+
+    let path = segment_path(dir.path(), 0);
+    let mut data = std::fs::read(&path).unwrap();
+    data[FILE_HEADER_LEN + VALUE_LEN_OFFSET + 2] ^= 0x10;
+    std::fs::write(&path, &data).unwrap();
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    match store.load() {
+        Err(ActionKvError::Corruption { offset, .. }) => {
+            assert_eq!(offset, FILE_HEADER_LEN as u64)
+        }
+        other => panic!("expected corruption, got {:?}", other),
+    }
+    drop(store);
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+
+    let options = Options {
+        recovery: RecoveryPolicy::Skip,
+        ..Options::default()
+    };
+    let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+    let report = store.load().unwrap();
+    assert_eq!(report.skipped, 1);
+    assert_eq!(report.records, 9);
+    assert!(!report.truncated);
+    assert_eq!(store.get(b"key0").unwrap(), None);
+    for i in 1..10 {
+        let key = format!("key{}", i);
+        assert_eq!(
+            store.get(key.as_bytes()).unwrap(),
+            Some(format!("value{}", i).into_bytes())
+        );
+    }
+}
+
+#[test]
+fn sealed_segment_is_never_truncated() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        max_segment_size: 200,
+        ..Options::default()
+    };
+    fill(dir.path(), options.clone(), 10);
+
+    let path = segment_path(dir.path(), 0);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let f = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    f.set_len(len - 3).unwrap();
+    drop(f);
+
+    let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+    assert!(matches!(store.load(), Err(ActionKvError::Truncated { .. })));
+    drop(store);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len - 3);
+}
+
+#[test]
+fn iter_log_reports_a_short_sealed_segment() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        max_segment_size: 200,
+        truncate_torn_tail: false,
+        ..Options::default()
+    };
+    fill(dir.path(), options.clone(), 10);
+
+    // Cut short the newest segment, which is only a torn write
+    let newest = ActionKV::open(dir.path()).unwrap().segments().len() - 1;
+    let path = segment_path(dir.path(), newest);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let f = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    f.set_len(len - 3).unwrap();
+    drop(f);
+
+    let mut store = ActionKV::open_with(dir.path(), options.clone()).unwrap();
+    store.load().unwrap();
+    let entries = store.iter_log().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len(), 9);
+    drop(store);
+
+    // The same in a sealed segment is damage
+    let path = segment_path(dir.path(), 0);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let f = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    f.set_len(len - 3).unwrap();
+    drop(f);
+
+    let store = ActionKV::open_with(dir.path(), options.clone()).unwrap();
+    let result = store.iter_log().collect::<Result<Vec<_>, _>>();
+    assert!(matches!(result, Err(ActionKvError::Truncated { .. })));
+    drop(store);
+
+    let options = Options {
+        recovery: RecoveryPolicy::Skip,
+        ..options
+    };
+    let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+    let report = store.load().unwrap();
+    assert_eq!(report.skipped, 1);
+    let entries = store.iter_log().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(entries.len() as u64, report.records);
+}
+
+#[test]
+fn check_counts_a_short_sealed_segment_as_corrupt() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        max_segment_size: 200,
+        ..Options::default()
+    };
+    fill(dir.path(), options, 10);
+
+    let sealed = segment_path(dir.path(), 0);
+    let len = std::fs::metadata(&sealed).unwrap().len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&sealed)
+        .unwrap()
+        .set_len(len - 3)
+        .unwrap();
+
+    let report = ActionKV::check(dir.path()).unwrap();
+    assert!(report.is_corrupt());
+    assert_eq!(report.corrupt, 1);
+    let segment = &report.segments[0];
+    assert!(segment.torn_tail.is_none());
+    assert_eq!(segment.corrupt[0].expected, None);
+
+    let active = segment_path(dir.path(), report.segments.len() - 1);
+    let len = std::fs::metadata(&active).unwrap().len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&active)
+        .unwrap()
+        .set_len(len - 3)
+        .unwrap();
+
+    let report = ActionKV::check(dir.path()).unwrap();
+    assert_eq!(report.corrupt, 1);
+    assert!(report.segments.last().unwrap().torn_tail.is_some());
+}
+
+#[test]
+fn torn_batch_is_discarded_whole() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), Options::default(), 3);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    let mut batch = WriteBatch::new();
+    batch.insert(b"key3", b"value3");
+    batch.delete(b"key0");
+    store.write_batch(batch).unwrap();
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    store.load().unwrap();
+    assert_eq!(store.get(b"key0").unwrap(), None);
+    assert_eq!(store.get(b"key3").unwrap(), Some(b"value3".to_vec()));
+    drop(store);
+
+    let path = segment_path(dir.path(), 0);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let f = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    f.set_len(len - 3).unwrap();
+    drop(f);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    let report = store.load().unwrap();
+    assert!(report.truncated);
+    assert_eq!(report.records, 3);
+    assert_eq!(store.get(b"key0").unwrap(), Some(b"value0".to_vec()));
+    assert_eq!(store.get(b"key3").unwrap(), None);
+}
+
+#[test]
+fn header_claiming_version_zero_is_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), Options::default(), 3);
+
+    let path = segment_path(dir.path(), 0);
+    let mut data = std::fs::read(&path).unwrap();
+    data[8..10].copy_from_slice(&0u16.to_le_bytes());
+    std::fs::write(&path, &data).unwrap();
+
+    assert!(matches!(
+        ActionKV::open(dir.path()),
+        Err(ActionKvError::Corruption { offset: 0, .. })
+    ));
+    let report = ActionKV::check(dir.path()).unwrap();
+    assert!(report.is_corrupt());
+    assert!(report.segments[0].header_error.is_some());
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use libactionkv::{ActionKV, ActionKvError, LostRegion, Options};
+
+/// Size of a segment's file header, after which its first record starts
+const FILE_HEADER_LEN: u64 = 23;
+
+/// Size of each record written by `fill`: a header, a four byte key and a six byte value
+const RECORD_LEN: u64 = 49 + 4 + 6;
+
+/// Offset of `key_len` within a record header
+const KEY_LEN_OFFSET: u64 = 8 + 1;
+
+fn fill(dir: &Path, n: u32) {
+    let mut store = ActionKV::open_with(dir, Options::default()).unwrap();
+    store.load().unwrap();
+    for i in 0..n {
+        let key = format!("key{}", i);
+        let value = format!("value{}", i);
+        store.insert(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn repair_leaves_an_intact_store_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), 5);
+
+    let report = ActionKV::repair(dir.path()).unwrap();
+    assert!(report.segments.is_empty());
+    assert_eq!(report.lost_bytes, 0);
+}
+
+#[test]
+fn repair_recovers_the_records_after_a_damaged_header() {
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), 8);
+
+    let path = ActionKV::open(dir.path()).unwrap().segments()[0]
+        .path
+        .clone();
+    let mut data = std::fs::read(&path).unwrap();
+    let damaged = FILE_HEADER_LEN + 3 * RECORD_LEN;
+    data[(damaged + KEY_LEN_OFFSET) as usize] ^= 0x40;
+    std::fs::write(&path, &data).unwrap();
+
+    // With its lengths gone, `load` can't find its way past the damaged record
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    assert!(matches!(
+        store.load(),
+        Err(ActionKvError::Corruption { offset, .. }) if offset == damaged
+    ));
+    drop(store);
+
+    let report = ActionKV::repair(dir.path()).unwrap();
+    assert_eq!(report.segments.len(), 1);
+    let segment = &report.segments[0];
+    assert_eq!(segment.path, path);
+    assert_eq!(segment.recovered, 7);
+    assert_eq!(
+        segment.lost,
+        [LostRegion {
+            offset: damaged,
+            bytes: RECORD_LEN,
+        }]
+    );
+    assert_eq!(report.lost_bytes, RECORD_LEN);
+    let backup = segment.backup.as_ref().unwrap();
+    assert_eq!(std::fs::read(backup).unwrap(), data);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    let load = store.load().unwrap();
+    assert_eq!(load.records, 7);
+    for i in 0..8 {
+        let key = format!("key{}", i);
+        let expected = (i != 3).then(|| format!("value{}", i).into_bytes());
+        assert_eq!(store.get(key.as_bytes()).unwrap(), expected);
+    }
+    drop(store);
+
+    let check = ActionKV::check(dir.path()).unwrap();
+    assert!(check.segments[0].corrupt.is_empty());
+}
@@ -0,0 +1,100 @@
+use std::path::Path;
+use std::thread;
+
+use libactionkv::{ActionKV, ActionKvError, Options, SharedActionKV};
+
+const THREADS: u32 = 4;
+const WRITES: u32 = 50;
+
+fn open(dir: &Path) -> ActionKV {
+    let options = Options {
+        max_segment_size: 1000,
+        ..Options::default()
+    };
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    store
+}
+
+#[test]
+fn writers_readers_and_compaction_share_a_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = SharedActionKV::new(open(dir.path()));
+
+    let mut handles = Vec::new();
+    for t in 0..THREADS {
+        let writer = store.clone();
+        handles.push(thread::spawn(move || {
+            for i in 0..WRITES {
+                let key = format!("t{}-{}", t, i);
+                writer.insert(key.as_bytes(), key.as_bytes()).unwrap();
+            }
+        }));
+
+        // Reads see a value in full or not at all
+        let reader = store.clone();
+        handles.push(thread::spawn(move || {
+            for i in 0..WRITES {
+                let key = format!("t{}-{}", t, i);
+                if let Some(value) = reader.get(key.as_bytes()).unwrap() {
+                    assert_eq!(value, key.as_bytes());
+                }
+            }
+        }));
+    }
+    let compactor = store.clone();
+    handles.push(thread::spawn(move || {
+        for _ in 0..5 {
+            compactor.compact().unwrap();
+            thread::yield_now();
+        }
+    }));
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(store.read().last_seq(), (THREADS * WRITES) as u64);
+    drop(store);
+
+    let store = open(dir.path());
+    assert_eq!(store.iter().count(), (THREADS * WRITES) as usize);
+    for t in 0..THREADS {
+        for i in 0..WRITES {
+            let key = format!("t{}-{}", t, i);
+            assert_eq!(store.get(key.as_bytes()).unwrap(), Some(key.into_bytes()));
+        }
+    }
+}
+
+#[test]
+fn compare_and_swap_serializes_increments() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = SharedActionKV::new(open(dir.path()));
+    store.insert(b"counter", b"0").unwrap();
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..WRITES {
+                    loop {
+                        let current = store.get(b"counter").unwrap().unwrap();
+                        let n: u32 = std::str::from_utf8(&current).unwrap().parse().unwrap();
+                        let next = (n + 1).to_string();
+                        match store.compare_and_swap(b"counter", Some(&current), next.as_bytes()) {
+                            Ok(()) => break,
+                            Err(ActionKvError::Conflict { .. }) => continue,
+                            Err(err) => panic!("{}", err),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let expected = (THREADS * WRITES).to_string();
+    assert_eq!(store.get(b"counter").unwrap(), Some(expected.into_bytes()));
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+
+use libactionkv::{ActionKV, IndexKind, Options, Snapshot};
+
+fn contents(snapshot: &Snapshot) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    snapshot
+        .iter()
+        .map(|kv| {
+            let kv = kv.unwrap();
+            (kv.key, kv.value)
+        })
+        .collect()
+}
+
+#[test]
+fn snapshot_is_unchanged_by_later_writes_and_compaction() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        max_segment_size: 200,
+        index: IndexKind::Ordered,
+        ..Options::default()
+    };
+    let mut store = ActionKV::open_with(dir.path(), options.clone()).unwrap();
+    store.load().unwrap();
+    for i in 0..10 {
+        let key = format!("key{}", i);
+        let value = format!("value{}", i);
+        store.insert(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+
+    let snapshot = store.snapshot().unwrap();
+    let before = contents(&snapshot);
+    let last_seq = snapshot.last_seq();
+    let log_len = snapshot.iter_log().count();
+    assert_eq!(before.len(), 10);
+
+    store.insert(b"key0", b"changed").unwrap();
+    store.delete(b"key1").unwrap();
+    store.insert(b"new", b"value").unwrap();
+    assert!(store.segments().len() > 2);
+    store.compact().unwrap();
+    assert_eq!(store.get(b"key0").unwrap(), Some(b"changed".to_vec()));
+    assert_eq!(store.get(b"key1").unwrap(), None);
+    drop(store);
+
+    assert_eq!(contents(&snapshot), before);
+    assert_eq!(snapshot.get(b"key0").unwrap(), Some(b"value0".to_vec()));
+    assert_eq!(snapshot.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+    assert_eq!(snapshot.get(b"new").unwrap(), None);
+    assert_eq!(snapshot.last_seq(), last_seq);
+    assert_eq!(snapshot.iter_log().count(), log_len);
+    let keys: Vec<_> = snapshot
+        .range(&b"key2"[..]..&b"key4"[..])
+        .map(|kv| kv.unwrap().key)
+        .collect();
+    assert_eq!(keys, [b"key2".to_vec(), b"key3".to_vec()]);
+
+    // The store itself moved on
+    let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+    store.load().unwrap();
+    assert_eq!(store.get(b"key0").unwrap(), Some(b"changed".to_vec()));
+    assert_eq!(store.get(b"new").unwrap(), Some(b"value".to_vec()));
+}
@@ -0,0 +1,127 @@
+use std::io::Read;
+use std::path::Path;
+
+use libactionkv::{ActionKV, ActionKvError, Options, WriteBatch};
+
+fn open(dir: &Path, options: Options) -> ActionKV {
+    let mut store = ActionKV::open_with(dir, options).unwrap();
+    store.load().unwrap();
+    store
+}
+
+/// A value several times larger than the buffer it is streamed through
+fn big_value() -> Vec<u8> {
+    (0..200_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn streamed_values_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path(), Options::default());
+    let value = big_value();
+    store
+        .insert_from_reader(b"big", &value[..], value.len() as u64)
+        .unwrap();
+    store.insert(b"small", b"value").unwrap();
+
+    let mut reader = store.get_reader(b"big").unwrap().unwrap();
+    assert_eq!(reader.key(), b"big");
+    assert_eq!(reader.len(), value.len() as u64);
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, value);
+    assert!(store.get_reader(b"missing").unwrap().is_none());
+    drop(store);
+
+    let store = open(dir.path(), Options::default());
+    assert_eq!(store.get(b"big").unwrap(), Some(value));
+    let mut read = Vec::new();
+    let mut reader = store.get_reader(b"small").unwrap().unwrap();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, b"value");
+}
+
+#[test]
+fn short_reader_inserts_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path(), Options::default());
+    store.insert(b"before", b"value").unwrap();
+    let len = store.segments()[0].len;
+
+    let value = big_value();
+    let result = store.insert_from_reader(b"big", &value[..], value.len() as u64 + 1);
+    assert!(matches!(result, Err(ActionKvError::Io(_))));
+    assert_eq!(store.get(b"big").unwrap(), None);
+    assert_eq!(store.segments()[0].len, len);
+
+    store.insert(b"after", b"value").unwrap();
+    drop(store);
+
+    let mut store = ActionKV::open(dir.path()).unwrap();
+    let report = store.load().unwrap();
+    assert!(!report.truncated);
+    assert_eq!(report.records, 2);
+    assert_eq!(store.get(b"after").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn damaged_streamed_value_fails_the_last_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path(), Options::default());
+    let value = big_value();
+    store
+        .insert_from_reader(b"big", &value[..], value.len() as u64)
+        .unwrap();
+
+    // Damaged after `load` has checked it
+    let path = store.segments()[0].path.clone();
+    let mut data = std::fs::read(&path).unwrap();
+    let middle = data.len() - value.len() / 2;
+    data[middle] ^= 0xff;
+    std::fs::write(&path, &data).unwrap();
+
+    let mut reader = store.get_reader(b"big").unwrap().unwrap();
+    let mut read = Vec::new();
+    let err = reader.read_to_end(&mut read).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn oversized_keys_and_values_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options {
+        max_key_size: 8,
+        max_value_size: 16,
+        ..Options::default()
+    };
+    let mut store = open(dir.path(), options);
+    let len = store.segments()[0].len;
+
+    assert!(matches!(
+        store.insert(b"a key too long", b"value"),
+        Err(ActionKvError::KeyTooLarge { len: 14, limit: 8 })
+    ));
+    assert!(matches!(
+        store.insert(b"key", &[0; 17]),
+        Err(ActionKvError::ValueTooLarge { len: 17, limit: 16 })
+    ));
+    assert!(matches!(
+        store.insert_from_reader(b"key", &[0; 17][..], 17),
+        Err(ActionKvError::ValueTooLarge { len: 17, limit: 16 })
+    ));
+
+    // One oversized write fails the whole batch
+    let mut batch = WriteBatch::new();
+    batch.insert(b"key", b"value");
+    batch.delete(b"a key too long");
+    assert!(matches!(
+        store.write_batch(batch),
+        Err(ActionKvError::KeyTooLarge { len: 14, limit: 8 })
+    ));
+
+    assert_eq!(store.segments()[0].len, len);
+    assert_eq!(store.get(b"key").unwrap(), None);
+
+    store.insert(b"12345678", &[0; 16]).unwrap();
+    assert_eq!(store.get(b"12345678").unwrap(), Some(vec![0; 16]));
+}
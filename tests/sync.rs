@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use libactionkv::{ActionKV, Options, SyncPolicy, WriteBatch};
+
+const POLICIES: [SyncPolicy; 6] = [
+    SyncPolicy::Never,
+    SyncPolicy::EveryWrite,
+    SyncPolicy::EveryNWrites(1),
+    SyncPolicy::EveryNWrites(3),
+    SyncPolicy::OnWriteAfter(Duration::ZERO),
+    SyncPolicy::OnWriteAfter(Duration::from_secs(3600)),
+];
+
+#[test]
+fn writes_survive_under_every_sync_policy() {
+    for sync in POLICIES {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            sync,
+            max_segment_size: 200,
+            ..Options::default()
+        };
+        let mut store = ActionKV::open_with(dir.path(), options.clone()).unwrap();
+        store.load().unwrap();
+        for i in 0..10 {
+            let key = format!("key{}", i);
+            store.insert(key.as_bytes(), b"value").unwrap();
+        }
+        store.delete(b"key0").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.insert(b"key1", b"batched");
+        batch.delete(b"key2");
+        store.write_batch(batch).unwrap();
+        store
+            .insert_from_reader(b"streamed", &b"value"[..], 5)
+            .unwrap();
+        drop(store);
+
+        let mut store = ActionKV::open_with(dir.path(), options).unwrap();
+        let report = store.load().unwrap();
+        assert!(!report.truncated, "{:?}", sync);
+        assert_eq!(store.get(b"key0").unwrap(), None, "{:?}", sync);
+        assert_eq!(
+            store.get(b"key1").unwrap(),
+            Some(b"batched".to_vec()),
+            "{:?}",
+            sync
+        );
+        assert_eq!(store.get(b"key2").unwrap(), None, "{:?}", sync);
+        assert_eq!(
+            store.get(b"key9").unwrap(),
+            Some(b"value".to_vec()),
+            "{:?}",
+            sync
+        );
+        assert_eq!(
+            store.get(b"streamed").unwrap(),
+            Some(b"value".to_vec()),
+            "{:?}",
+            sync
+        );
+        assert_eq!(store.iter().count(), 9, "{:?}", sync);
+
+        // An explicit sync works whatever the policy
+        store.insert(b"after", b"value").unwrap();
+        store.sync().unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+use std::thread;
+
+use libactionkv::{ActionKV, ActionKvError, Options, Result, SharedActionKV};
+
+fn open(dir: &Path) -> ActionKV {
+    let mut store = ActionKV::open_with(dir, Options::default()).unwrap();
+    store.load().unwrap();
+    store
+}
+
+/// Writes `key` from another thread through its own handle on `store`
+fn write_elsewhere(store: &SharedActionKV, key: &'static [u8], value: &'static [u8]) {
+    let other = store.clone();
+    thread::spawn(move || other.insert(key, value).unwrap())
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn transaction_commits_its_writes_together() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path());
+    store.insert(b"a", b"10").unwrap();
+    store.insert(b"b", b"0").unwrap();
+
+    let moved = store
+        .transaction(|txn| {
+            let a = txn.get(b"a")?.unwrap();
+            txn.insert(b"a", b"7");
+            txn.insert(b"b", b"3");
+            txn.delete(b"c");
+            // Reads see the transaction's own writes
+            assert_eq!(txn.get(b"a")?, Some(b"7".to_vec()));
+            Ok(a)
+        })
+        .unwrap();
+    assert_eq!(moved, b"10");
+
+    // A failing transaction writes nothing
+    let last_seq = store.last_seq();
+    let result: Result<()> = store.transaction(|txn| {
+        txn.insert(b"a", b"0");
+        Err(ActionKvError::Conflict { key: b"a".to_vec() })
+    });
+    assert!(result.is_err());
+    assert_eq!(store.last_seq(), last_seq);
+    drop(store);
+
+    let store = open(dir.path());
+    assert_eq!(store.get(b"a").unwrap(), Some(b"7".to_vec()));
+    assert_eq!(store.get(b"b").unwrap(), Some(b"3".to_vec()));
+}
+
+#[test]
+fn concurrent_write_to_a_read_key_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = SharedActionKV::new(open(dir.path()));
+    store.insert(b"k", b"1").unwrap();
+
+    let result = store.transaction(|txn| {
+        let value = txn.get(b"k")?.unwrap();
+        write_elsewhere(&store, b"k", b"2");
+        // Still the value as first read
+        assert_eq!(txn.get(b"k")?, Some(value));
+        txn.insert(b"k", b"from transaction");
+        txn.insert(b"other", b"from transaction");
+        Ok(())
+    });
+    assert!(matches!(result, Err(ActionKvError::Conflict { key }) if key == b"k"));
+    assert_eq!(store.get(b"k").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(store.get(b"other").unwrap(), None);
+
+    // So does one creating a key that was missing when read
+    let result = store.transaction(|txn| {
+        assert_eq!(txn.get(b"new")?, None);
+        write_elsewhere(&store, b"new", b"elsewhere");
+        txn.insert(b"new", b"from transaction");
+        Ok(())
+    });
+    assert!(matches!(result, Err(ActionKvError::Conflict { key }) if key == b"new"));
+    assert_eq!(store.get(b"new").unwrap(), Some(b"elsewhere".to_vec()));
+
+    // A write to a key the transaction never read doesn't get in the way
+    store
+        .transaction(|txn| {
+            txn.get(b"k")?;
+            write_elsewhere(&store, b"unrelated", b"value");
+            txn.insert(b"k", b"3");
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(store.get(b"k").unwrap(), Some(b"3".to_vec()));
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use libactionkv::{ActionKV, Options};
+
+const SHORT: Duration = Duration::from_millis(50);
+const LONG: Duration = Duration::from_secs(3600);
+
+fn open(dir: &Path) -> ActionKV {
+    let mut store = ActionKV::open_with(dir, Options::default()).unwrap();
+    store.load().unwrap();
+    store
+}
+
+#[test]
+fn values_disappear_once_expired() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path());
+    store.insert(b"old", b"before").unwrap();
+    store.insert_with_ttl(b"old", b"short", SHORT).unwrap();
+    store.insert_with_ttl(b"long", b"value", LONG).unwrap();
+    store.insert(b"forever", b"value").unwrap();
+
+    let kv = store.get_with_meta(b"old").unwrap().unwrap();
+    assert_eq!(kv.value, b"short");
+    assert!(kv.expires_at.unwrap() > SystemTime::now());
+    let kv = store.get_with_meta(b"forever").unwrap().unwrap();
+    assert_eq!(kv.expires_at, None);
+
+    thread::sleep(SHORT * 2);
+    assert_eq!(store.get(b"old").unwrap(), None);
+    assert!(store.get_reader(b"old").unwrap().is_none());
+    assert_eq!(store.get(b"long").unwrap(), Some(b"value".to_vec()));
+    let keys: Vec<_> = store.iter().map(|kv| kv.unwrap().key).collect();
+    assert_eq!(keys.len(), 2);
+    assert!(!keys.contains(&b"old".to_vec()));
+    drop(store);
+
+    // The expired value still hides the one it replaced
+    let store = open(dir.path());
+    assert_eq!(store.get(b"old").unwrap(), None);
+    assert_eq!(store.get(b"long").unwrap(), Some(b"value".to_vec()));
+}
+
+#[test]
+fn compaction_drops_expired_values() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = open(dir.path());
+    store.insert(b"old", b"before").unwrap();
+    store.insert_with_ttl(b"old", b"short", SHORT).unwrap();
+    store.insert_with_ttl(b"long", b"value", LONG).unwrap();
+    thread::sleep(SHORT * 2);
+
+    store.compact().unwrap();
+    assert_eq!(store.get(b"old").unwrap(), None);
+    assert_eq!(store.get(b"long").unwrap(), Some(b"value".to_vec()));
+    drop(store);
+
+    let store = open(dir.path());
+    assert_eq!(store.get(b"old").unwrap(), None);
+    let log: Vec<_> = store.iter_log().map(|entry| entry.unwrap().key).collect();
+    assert_eq!(log, [b"long".to_vec()]);
+    let kv = store.get_with_meta(b"long").unwrap().unwrap();
+    assert!(kv.expires_at.unwrap() > SystemTime::now() + LONG / 2);
+}
@@ -0,0 +1,205 @@
+//! Stores written by earlier versions of the crate, in `tests/fixtures`, are upgraded on
+//! open and read back
+//!
+//! Each fixture was written with the same sequence of operations, as far as the version
+//! that wrote it supported them:
+//!
+//! - `apple` = `red`, `banana` = `yellow`, `cherry` = `dark red`, `banana` = `green`, then
+//!   `cherry` deleted
+//! - from v0: a batch inserting `date` = `brown` and `elder` = `black` and deleting
+//!   `apple`, then `apple` = `green`
+//! - from v4: `fig` = 1000 `f`s, compressed with zstd
+//! - from v5: `grape` = `purple` with a TTL of a century, and `ghost` = `boo` with one that
+//!   had run out by the time the writer exited
+//!
+//! The directory fixtures are split into segments of at most 150 bytes.
+
+use std::path::{Path, PathBuf};
+
+use libactionkv::{ActionKV, ActionKvError, EncryptionKey, Options, FORMAT_VERSION};
+
+fn copy_fixture(name: &str, to: &Path) -> PathBuf {
+    let from = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    let dest = to.join(name);
+    if from.is_dir() {
+        std::fs::create_dir(&dest).unwrap();
+        for entry in std::fs::read_dir(&from).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), dest.join(entry.file_name())).unwrap();
+        }
+    } else {
+        std::fs::copy(&from, &dest).unwrap();
+    }
+    dest
+}
+
+/// Copies the fixture `name`, opens it with `options` and hands it to `check`, then does
+/// the same again to make sure the upgrade stuck
+fn upgrade(name: &str, options: Options, check: impl Fn(&ActionKV)) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = copy_fixture(name, dir.path());
+
+    if path.is_dir() {
+        let report = ActionKV::check(&path).unwrap();
+        assert!(!report.is_corrupt(), "{:?}", report);
+        assert!(report.segments.iter().all(|segment| segment.outdated));
+    }
+
+    for _ in 0..2 {
+        let mut store = ActionKV::open_with(&path, options.clone()).unwrap();
+        store.load().unwrap();
+        for segment in store.segments() {
+            assert_eq!(segment.format_version, FORMAT_VERSION);
+        }
+        check(&store);
+    }
+
+    let report = ActionKV::check(&path).unwrap();
+    assert!(!report.is_corrupt(), "{:?}", report);
+}
+
+fn get(store: &ActionKV, key: &[u8]) -> Option<Vec<u8>> {
+    store.get(key).unwrap()
+}
+
+fn check_basics(store: &ActionKV) {
+    assert_eq!(get(store, b"banana"), Some(b"green".to_vec()));
+    assert_eq!(get(store, b"cherry"), None);
+}
+
+fn check_batch(store: &ActionKV) {
+    check_basics(store);
+    assert_eq!(get(store, b"apple"), Some(b"green".to_vec()));
+    assert_eq!(get(store, b"date"), Some(b"brown".to_vec()));
+    assert_eq!(get(store, b"elder"), Some(b"black".to_vec()));
+}
+
+fn check_compressed(store: &ActionKV) {
+    check_batch(store);
+    assert_eq!(get(store, b"fig"), Some(vec![b'f'; 1000]));
+}
+
+fn check_ttl(store: &ActionKV) {
+    check_compressed(store);
+    assert_eq!(get(store, b"grape"), Some(b"purple".to_vec()));
+    assert_eq!(get(store, b"ghost"), None);
+}
+
+#[test]
+fn single_file_baseline() {
+    // Deletes were written as empty values before tombstones were added
+    upgrade("single-file-baseline.db", Options::default(), |store| {
+        assert_eq!(get(store, b"apple"), Some(b"red".to_vec()));
+        assert_eq!(get(store, b"banana"), Some(b"green".to_vec()));
+        assert_eq!(get(store, b"cherry"), Some(Vec::new()));
+    });
+}
+
+#[test]
+fn single_file_with_tombstones() {
+    upgrade("single-file-tombstones.db", Options::default(), |store| {
+        check_basics(store);
+        assert_eq!(get(store, b"apple"), Some(b"red".to_vec()));
+    });
+}
+
+#[test]
+fn v0_headerless_segments() {
+    upgrade("v0", Options::default(), check_batch);
+}
+
+#[test]
+fn v1() {
+    upgrade("v1", Options::default(), check_batch);
+}
+
+#[test]
+fn v2() {
+    upgrade("v2", Options::default(), check_batch);
+}
+
+#[test]
+fn v3_xxhash64() {
+    upgrade("v3", Options::default(), check_batch);
+}
+
+#[test]
+fn v4_crc32c_compressed() {
+    upgrade("v4", Options::default(), check_compressed);
+}
+
+#[test]
+fn v4_encrypted() {
+    let options = Options {
+        encryption_key: Some(EncryptionKey::new([7; 32])),
+        require_encryption: true,
+        ..Options::default()
+    };
+    upgrade("v4-encrypted", options, check_compressed);
+
+    upgrade("v4-encrypted", Options::default(), |store| {
+        assert!(matches!(
+            store.get(b"apple"),
+            Err(ActionKvError::Decryption { .. })
+        ));
+    });
+}
+
+#[test]
+fn v5_ttl() {
+    upgrade("v5", Options::default(), check_ttl);
+}
+
+#[test]
+fn v6_sequence_numbers() {
+    upgrade("v6", Options::default(), |store| {
+        check_ttl(store);
+        let banana = store.get_with_meta(b"banana").unwrap().unwrap();
+        assert_eq!(banana.seq, 4);
+        assert!(banana.timestamp.is_some());
+        assert!(store.last_seq() > banana.seq);
+    });
+}
+
+/// Offset of the second record in `single-file-tombstones.db`, after `apple` = `red`
+const SECOND_RECORD: u64 = 12 + 5 + 3;
+
+#[test]
+fn damaged_length_fails_the_upgrade() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = copy_fixture("single-file-tombstones.db", dir.path());
+    let mut data = std::fs::read(&path).unwrap();
+    data[SECOND_RECORD as usize + 8 + 3] ^= 0x01;
+    std::fs::write(&path, &data).unwrap();
+
+    match ActionKV::open(&path) {
+        Err(ActionKvError::Truncated { offset }) => assert_eq!(offset, SECOND_RECORD),
+        other => panic!("expected the upgrade to fail, got {:?}", other),
+    }
+    let segment = path.join("00000001.log");
+    assert_eq!(std::fs::read(&segment).unwrap(), data);
+
+    let report = ActionKV::check(&path).unwrap();
+    assert!(report.is_corrupt());
+    assert_eq!(report.segments[0].corrupt[0].offset, SECOND_RECORD);
+}
+
+#[test]
+fn torn_tail_survives_the_upgrade() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = copy_fixture("single-file-tombstones.db", dir.path());
+    let data = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &data[..data.len() - 3]).unwrap();
+
+    let mut store = ActionKV::open(&path).unwrap();
+    let report = store.load().unwrap();
+    assert!(report.truncated);
+    assert_eq!(get(&store, b"apple"), Some(b"red".to_vec()));
+    assert_eq!(get(&store, b"banana"), Some(b"green".to_vec()));
+    assert_eq!(get(&store, b"cherry"), Some(b"dark red".to_vec()));
+
+    let backup = std::fs::read(path.join("00000001.log.corrupt")).unwrap();
+    assert_eq!(backup, data[..data.len() - 3]);
+}